@@ -0,0 +1,194 @@
+use core::fmt;
+use std::str::FromStr;
+
+use k256::ecdsa::VerifyingKey;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+pub const ADDRESS_LEN: usize = 20;
+
+#[derive(Debug, PartialEq)]
+pub enum AddressError {
+    InvalidFormat,
+    InvalidChecksum,
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressError::InvalidFormat => write!(f, "expected 0x followed by 40 hex digits"),
+            AddressError::InvalidChecksum => write!(f, "invalid address checksum"),
+        }
+    }
+}
+
+impl std::error::Error for AddressError {}
+
+/// Account identifier: the last 20 bytes of the SHA-256 hash of the
+/// compressed public key.
+///
+/// Written as `0x` followed by 40 hex digits whose letters are upper-cased
+/// according to the hash of the digits themselves, like Ethereum's EIP-55,
+/// so that most typos break the checksum.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Address([u8; ADDRESS_LEN]);
+
+impl Address {
+    pub const fn from_bytes(bytes: [u8; ADDRESS_LEN]) -> Address {
+        Address(bytes)
+    }
+
+    pub fn from_public_key(public_key: &VerifyingKey) -> Address {
+        let key_hash =
+            hex::decode(sha256::digest(public_key.to_encoded_point(true).as_bytes())).unwrap();
+        let mut bytes = [0; ADDRESS_LEN];
+        bytes.copy_from_slice(&key_hash[key_hash.len() - ADDRESS_LEN..]);
+        Address(bytes)
+    }
+
+    /// Parses an address typed by a user, which must be checksummed: a
+    /// single-case one may hide a typo and send money to a fresh account.
+    pub fn parse_checksummed(s: &str) -> Result<Address, AddressError> {
+        let address: Address = s.parse()?;
+        if s != address.to_string() {
+            return Err(AddressError::InvalidChecksum);
+        }
+        Ok(address)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; ADDRESS_LEN] {
+        &self.0
+    }
+
+    /// Address standing for a named account in the tests.
+    #[cfg(test)]
+    pub fn named(name: &str) -> Address {
+        let mut bytes = [0; ADDRESS_LEN];
+        bytes.copy_from_slice(&hex::decode(sha256::digest(name)).unwrap()[..ADDRESS_LEN]);
+        Address(bytes)
+    }
+}
+
+fn to_checksum_hex(bytes: &[u8]) -> String {
+    let hex = hex::encode(bytes);
+    let hex_hash = sha256::digest(&hex);
+    hex.chars()
+        .zip(hex_hash.chars())
+        .map(|(c, h)| match h.to_digit(16) {
+            Some(digit) if digit >= 8 => c.to_ascii_uppercase(),
+            _ => c,
+        })
+        .collect()
+}
+
+impl FromStr for Address {
+    type Err = AddressError;
+
+    /// Accepts the checksummed form, or a single-case one which carries no
+    /// checksum.
+    fn from_str(s: &str) -> Result<Address, AddressError> {
+        let digits = s.strip_prefix("0x").ok_or(AddressError::InvalidFormat)?;
+        if digits.len() != 2 * ADDRESS_LEN {
+            return Err(AddressError::InvalidFormat);
+        }
+        let mut bytes = [0; ADDRESS_LEN];
+        hex::decode_to_slice(digits, &mut bytes).map_err(|_| AddressError::InvalidFormat)?;
+        let is_mixed_case = digits.chars().any(|c| c.is_ascii_lowercase())
+            && digits.chars().any(|c| c.is_ascii_uppercase());
+        if is_mixed_case && digits != to_checksum_hex(&bytes) {
+            return Err(AddressError::InvalidChecksum);
+        }
+        Ok(Address(bytes))
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{}", to_checksum_hex(&self.0))
+    }
+}
+
+impl fmt::Debug for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl Serialize for Address {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Address, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use k256::ecdsa::SigningKey;
+    use rand_core::OsRng;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_checks_format_and_checksum() {
+        let key = SigningKey::random(&mut OsRng);
+        let address = Address::from_public_key(key.verifying_key());
+        let s = address.to_string();
+        assert_eq!(s.len(), 42);
+        assert_eq!(s.parse(), Ok(address));
+        assert_eq!(s.to_lowercase().parse(), Ok(address));
+        assert_eq!(format!("0x{}", s[2..].to_uppercase()).parse(), Ok(address));
+
+        // Changing the case of a single letter breaks the checksum
+        let s = Address::named("adel.eth").to_string();
+        let i = s[2..].find(|c: char| c.is_ascii_alphabetic()).unwrap() + 2;
+        let typo: String = s
+            .char_indices()
+            .map(|(j, c)| match j == i {
+                true if c.is_ascii_lowercase() => c.to_ascii_uppercase(),
+                true => c.to_ascii_lowercase(),
+                false => c,
+            })
+            .collect();
+        assert_eq!(typo.parse::<Address>(), Err(AddressError::InvalidChecksum));
+
+        for invalid in ["adel.eth", "", "0x", &s[2..], &s[..41], &format!("{}0", s)] {
+            assert_eq!(invalid.parse::<Address>(), Err(AddressError::InvalidFormat));
+        }
+    }
+
+    #[test]
+    fn test_parse_checksummed_catches_lowercase_typos() {
+        let address = Address::named("adel.eth");
+        let s = address.to_string();
+        assert_eq!(Address::parse_checksummed(&s), Ok(address));
+
+        let lowercase = s.to_lowercase();
+        let last = if lowercase.ends_with('0') { "1" } else { "0" };
+        let typo = format!("{}{}", &lowercase[..41], last);
+        assert!(typo.parse::<Address>().is_ok());
+        for unchecked in [&lowercase, &typo] {
+            assert_eq!(
+                Address::parse_checksummed(unchecked),
+                Err(AddressError::InvalidChecksum)
+            );
+        }
+        assert_eq!(
+            Address::parse_checksummed("adel.eth"),
+            Err(AddressError::InvalidFormat)
+        );
+    }
+
+    #[test]
+    fn test_serde_as_string() {
+        let address = Address::named("adel.eth");
+        let json = serde_json::to_string(&address).unwrap();
+        assert_eq!(json, format!("\"{}\"", address));
+        assert_eq!(serde_json::from_str::<Address>(&json).unwrap(), address);
+        assert!(serde_json::from_str::<Address>("\"adel.eth\"").is_err());
+    }
+}
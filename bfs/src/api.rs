@@ -0,0 +1,614 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
+
+use bigdecimal::BigDecimal;
+use serde::Serialize;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::address::Address;
+use crate::block::Block;
+use crate::blockchain::Blockchain;
+use crate::hashable::Hashable;
+use crate::mempool::{Mempool, MempoolError};
+use crate::merkle_tree::MerkleProof;
+use crate::p2p::Node;
+use crate::pow::get_difficulty;
+use crate::transaction::Transaction;
+
+pub const API_DEFAULT_ADDRESS: &str = "127.0.0.1:5000";
+pub const API_LATEST_BLOCKS_DEFAULT: usize = 10;
+
+#[derive(Debug, PartialEq)]
+pub enum ApiError {
+    NotFound,
+    BadRequest,
+    MethodNotAllowed,
+    RejectedTransaction(MempoolError),
+}
+
+impl ApiError {
+    pub fn status_code(&self) -> u16 {
+        match self {
+            ApiError::NotFound => 404,
+            ApiError::BadRequest => 400,
+            ApiError::MethodNotAllowed => 405,
+            ApiError::RejectedTransaction(_) => 422,
+        }
+    }
+
+    fn message(&self) -> &'static str {
+        match self {
+            ApiError::NotFound => "not found",
+            ApiError::BadRequest => "bad request",
+            ApiError::MethodNotAllowed => "method not allowed",
+            ApiError::RejectedTransaction(_) => "rejected transaction",
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct BlockSummary {
+    pub number: u64,
+    pub hash: String,
+    pub root: String,
+    pub prev_hash: String,
+    pub n_txs: usize,
+    pub volume: BigDecimal,
+    pub total_fees: BigDecimal,
+    pub mined: bool,
+    pub time: i64,
+    pub reward: BigDecimal,
+    pub diff: String,
+}
+
+impl From<&Block> for BlockSummary {
+    fn from(block: &Block) -> BlockSummary {
+        BlockSummary {
+            number: block.block_header.block_number,
+            hash: block.block_hash.clone(),
+            root: block.block_header.hash.clone(),
+            prev_hash: block.block_header.prev_hash.clone(),
+            n_txs: block.txs.len(),
+            volume: block.block_info.volume.clone(),
+            total_fees: block.block_info.total_fees.clone(),
+            mined: block.block_header.mined,
+            time: block.block_header.created_at,
+            reward: block.block_header.reward.clone(),
+            diff: get_difficulty(block.block_header.bits).to_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct BlockDetails {
+    #[serde(flatten)]
+    pub summary: BlockSummary,
+    pub txs: Vec<TxDetails>,
+}
+
+impl From<&Block> for BlockDetails {
+    fn from(block: &Block) -> BlockDetails {
+        BlockDetails {
+            summary: BlockSummary::from(block),
+            txs: block
+                .txs
+                .iter()
+                .map(|(hash, tx)| TxDetails::new(block, hash, tx))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct TxDetails {
+    pub hash: String,
+    pub number: u64,
+    pub block_hash: String,
+    pub fr: Address,
+    pub to: Address,
+    pub value: BigDecimal,
+    pub fee: BigDecimal,
+    pub nonce: u64,
+    pub time: i64,
+    pub signed: bool,
+    pub signature: Option<String>,
+}
+
+impl TxDetails {
+    pub fn new(block: &Block, tx_hash: &str, tx: &Transaction) -> TxDetails {
+        TxDetails {
+            hash: tx_hash.to_string(),
+            number: block.block_header.block_number,
+            block_hash: block.block_hash.clone(),
+            fr: tx.from,
+            to: tx.to,
+            value: tx.value.clone(),
+            fee: tx.fee.clone(),
+            nonce: tx.nonce,
+            time: tx.time,
+            signed: tx.signed,
+            signature: tx.signature.map(|signature| signature.to_string()),
+        }
+    }
+}
+
+/// A transaction of the mempool, not part of any block yet.
+#[derive(Serialize)]
+pub struct PendingTx {
+    pub hash: String,
+    pub fr: Address,
+    pub to: Address,
+    pub value: BigDecimal,
+    pub fee: BigDecimal,
+    pub nonce: u64,
+    pub time: i64,
+    pub signed: bool,
+}
+
+impl PendingTx {
+    pub fn new(tx_hash: &str, tx: &Transaction) -> PendingTx {
+        PendingTx {
+            hash: tx_hash.to_string(),
+            fr: tx.from,
+            to: tx.to,
+            value: tx.value.clone(),
+            fee: tx.fee.clone(),
+            nonce: tx.nonce,
+            time: tx.time,
+            signed: tx.signed,
+        }
+    }
+}
+
+/// Proof that a transaction is part of a block, to check against its root.
+#[derive(Serialize)]
+pub struct TxProof {
+    pub number: u64,
+    pub block_hash: String,
+    pub root: String,
+    pub txs_number: u64,
+    pub proof: MerkleProof,
+}
+
+#[derive(Serialize)]
+pub struct SubmittedTx {
+    pub hash: String,
+}
+
+#[derive(Serialize)]
+pub struct AccountDetails {
+    pub address: Address,
+    pub balance: BigDecimal,
+    pub nonce: u64,
+}
+
+/// Resolves a `GET` path against the blockchain and returns the JSON body.
+///
+/// Routes mirror what the block explorer fetches:
+/// - `/blocks[?limit=N]`: latest blocks, newest first
+/// - `/block/<number|hash>`: a block with its transactions
+/// - `/tx/<hash>` or `/tx/<block_number>/<hash>`: a transaction
+/// - `/proof/<hash>`: Merkle proof of inclusion of a transaction
+/// - `/acc/<address>`: balance & nonce of an account
+/// - `/txs_pool`: pending transactions, highest fee first
+/// - `/tx_pool/<hash>`: a pending transaction
+///
+/// Transactions are submitted with `POST /tx`, see `submit_tx`.
+pub fn route(blockchain: &Blockchain, mempool: &Mempool, url: &str) -> Result<String, ApiError> {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    match segments.as_slice() {
+        ["blocks"] => {
+            let limit = match query_param(query, "limit") {
+                Some(limit) => limit.parse().map_err(|_| ApiError::BadRequest)?,
+                None => API_LATEST_BLOCKS_DEFAULT,
+            };
+            let blocks: Vec<BlockSummary> = blockchain
+                .get_latest_blocks(limit)
+                .into_iter()
+                .map(BlockSummary::from)
+                .collect();
+            Ok(to_json(&blocks))
+        }
+        ["block", id] => {
+            let block = match id.parse::<u64>() {
+                Ok(block_number) => blockchain.get_block(block_number),
+                Err(_) => blockchain.get_block_by_hash(id),
+            };
+            let block = block.ok_or(ApiError::NotFound)?;
+            Ok(to_json(&BlockDetails::from(block)))
+        }
+        ["tx", tx_hash] | ["tx", _, tx_hash] => {
+            let (block, tx) = blockchain.find_tx(tx_hash).ok_or(ApiError::NotFound)?;
+            Ok(to_json(&TxDetails::new(block, tx_hash, tx)))
+        }
+        ["proof", tx_hash] => {
+            let (block, _) = blockchain.find_tx(tx_hash).ok_or(ApiError::NotFound)?;
+            let proof = block
+                .merkle_tree
+                .get_proof(tx_hash)
+                .ok_or(ApiError::NotFound)?;
+            Ok(to_json(&TxProof {
+                number: block.block_header.block_number,
+                block_hash: block.block_hash.clone(),
+                root: block.block_header.hash.clone(),
+                txs_number: block.block_header.txs_number,
+                proof,
+            }))
+        }
+        ["acc", address] => {
+            let address: Address = address.parse().map_err(|_| ApiError::BadRequest)?;
+            if blockchain.state.is_new(&address) {
+                return Err(ApiError::NotFound);
+            }
+            let account = blockchain.state.get(&address);
+            Ok(to_json(&AccountDetails {
+                address,
+                balance: account.balance.clone(),
+                nonce: account.nonce,
+            }))
+        }
+        ["txs_pool"] => {
+            let mut txs: Vec<(&String, &Transaction)> = mempool.iter().collect();
+            txs.sort_by(|(hash_a, a), (hash_b, b)| b.fee.cmp(&a.fee).then(hash_a.cmp(hash_b)));
+            let txs: Vec<PendingTx> = txs
+                .into_iter()
+                .map(|(tx_hash, tx)| PendingTx::new(tx_hash, tx))
+                .collect();
+            Ok(to_json(&txs))
+        }
+        ["tx_pool", tx_hash] => {
+            let tx = mempool.get(tx_hash).ok_or(ApiError::NotFound)?;
+            Ok(to_json(&PendingTx::new(tx_hash, tx)))
+        }
+        _ => Err(ApiError::NotFound),
+    }
+}
+
+/// Adds a signed transaction, sent as JSON to `POST /tx`, to the mempool and
+/// returns it. Its recipient must be written in its checksummed form.
+pub fn submit_tx(
+    blockchain: &Blockchain,
+    mempool: &mut Mempool,
+    url: &str,
+    body: &str,
+) -> Result<Transaction, ApiError> {
+    if url != "/tx" {
+        return Err(ApiError::NotFound);
+    }
+    let tx: serde_json::Value = serde_json::from_str(body).map_err(|_| ApiError::BadRequest)?;
+    let to = tx["to"].as_str().ok_or(ApiError::BadRequest)?;
+    Address::parse_checksummed(to).map_err(|_| ApiError::BadRequest)?;
+    let tx: Transaction = serde_json::from_value(tx).map_err(|_| ApiError::BadRequest)?;
+    mempool
+        .add(tx.clone(), &blockchain.state)
+        .map_err(ApiError::RejectedTransaction)?;
+    Ok(tx)
+}
+
+fn query_param<'a>(query: &'a str, key: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| v)
+}
+
+fn to_json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap()
+}
+
+pub struct ApiServer {
+    server: Server,
+    blockchain: Arc<RwLock<Blockchain>>,
+    mempool: Arc<RwLock<Mempool>>,
+    /// Node announcing the submitted transactions to its peers, if any.
+    pub node: Option<Node>,
+}
+
+impl ApiServer {
+    pub fn bind(
+        address: &str,
+        blockchain: Arc<RwLock<Blockchain>>,
+        mempool: Arc<RwLock<Mempool>>,
+    ) -> io::Result<ApiServer> {
+        let server = Server::http(address).map_err(io::Error::other)?;
+        Ok(ApiServer {
+            server,
+            blockchain,
+            mempool,
+            node: None,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.server.server_addr().to_ip().unwrap()
+    }
+
+    /// Blocks the current thread, answering incoming requests forever.
+    pub fn serve(&self) {
+        for request in self.server.incoming_requests() {
+            self.handle(request);
+        }
+    }
+
+    pub fn spawn(self) -> JoinHandle<()> {
+        thread::spawn(move || self.serve())
+    }
+
+    fn handle(&self, mut request: Request) {
+        let result = match request.method() {
+            Method::Get => route(
+                &self.blockchain.read().unwrap(),
+                &self.mempool.read().unwrap(),
+                request.url(),
+            ),
+            Method::Post => {
+                let mut body = String::new();
+                match request.as_reader().read_to_string(&mut body) {
+                    Ok(_) => self.submit(request.url(), &body),
+                    Err(_) => Err(ApiError::BadRequest),
+                }
+            }
+            _ => Err(ApiError::MethodNotAllowed),
+        };
+        let response = match result {
+            Ok(body) => Response::from_string(body),
+            Err(err) => Response::from_string(format!(r#"{{"error":"{}"}}"#, err.message()))
+                .with_status_code(err.status_code()),
+        };
+        let response = response
+            .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
+            .with_header(Header::from_bytes("Access-Control-Allow-Origin", "*").unwrap());
+        // The client may already be gone, nothing to do about it.
+        let _ = request.respond(response);
+    }
+
+    fn submit(&self, url: &str, body: &str) -> Result<String, ApiError> {
+        let tx = submit_tx(
+            &self.blockchain.read().unwrap(),
+            &mut self.mempool.write().unwrap(),
+            url,
+            body,
+        )?;
+        if let Some(node) = &self.node {
+            node.announce_tx(&tx);
+        }
+        Ok(to_json(&SubmittedTx {
+            hash: tx.get_hash(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::time::{Duration, Instant};
+
+    use serde_json::Value;
+
+    use super::*;
+    use crate::transaction::ALLOCATION_SENDER;
+    use crate::wallet::Wallet;
+
+    fn get_test_blockchain() -> (Blockchain, Wallet) {
+        let wallet = Wallet::new();
+        let txs = [
+            wallet.address(),
+            Address::named("adel.eth"),
+            Address::named("aihe.eth"),
+        ]
+        .map(|address| Transaction::allocation(address, 100_000.into(), 0));
+        let genesis_block = Block::genesis(&txs).unwrap();
+        (
+            Blockchain::from_genesis_block(genesis_block).unwrap(),
+            wallet,
+        )
+    }
+
+    fn http_get(address: SocketAddr, path: &str) -> (u16, Value) {
+        http_request(address, "GET", path, "")
+    }
+
+    fn http_request(address: SocketAddr, method: &str, path: &str, body: &str) -> (u16, Value) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    /// Fields read by the block explorer, see `block-explorer/src/components`.
+    fn assert_fields(value: &Value, fields: &[&str]) {
+        for field in fields {
+            assert!(!value[field].is_null(), "missing field {}", field);
+        }
+    }
+
+    #[test]
+    fn test_route_tx_proof() {
+        let (blockchain, _) = get_test_blockchain();
+        let genesis = blockchain.get_last_block();
+        let tx_hash = genesis.txs.keys().nth(1).unwrap();
+        let body = route(&blockchain, &Mempool::new(), &format!("/proof/{}", tx_hash)).unwrap();
+        let body: Value = serde_json::from_str(&body).unwrap();
+        let proof: MerkleProof = serde_json::from_value(body["proof"].clone()).unwrap();
+        assert_eq!(&proof.tx_hash, tx_hash);
+        assert!(proof.verify(&genesis.block_header.hash, genesis.block_header.txs_number));
+        assert_eq!(
+            route(&blockchain, &Mempool::new(), "/proof/unknown"),
+            Err(ApiError::NotFound)
+        );
+    }
+
+    #[test]
+    fn test_route_block_by_number_and_hash() {
+        let (blockchain, _) = get_test_blockchain();
+        let genesis_hash = blockchain.get_last_block().block_hash.clone();
+        let by_number = route(&blockchain, &Mempool::new(), "/block/0").unwrap();
+        let by_hash = route(
+            &blockchain,
+            &Mempool::new(),
+            &format!("/block/{}", genesis_hash),
+        )
+        .unwrap();
+        assert_eq!(by_number, by_hash);
+        assert_eq!(
+            route(&blockchain, &Mempool::new(), "/block/1"),
+            Err(ApiError::NotFound)
+        );
+        assert_eq!(
+            route(&blockchain, &Mempool::new(), "/blocks?limit=x"),
+            Err(ApiError::BadRequest)
+        );
+    }
+
+    #[test]
+    fn test_server_answers_explorer_lookups() {
+        let (blockchain, wallet) = get_test_blockchain();
+        let genesis = blockchain.get_last_block();
+        let tx_hash = genesis.txs.keys().next().unwrap().clone();
+        let genesis_hash = genesis.block_hash.clone();
+        let header_hash = genesis.block_header.get_hash();
+
+        let server = ApiServer::bind(
+            "127.0.0.1:0",
+            Arc::new(RwLock::new(blockchain)),
+            Arc::default(),
+        )
+        .unwrap();
+        let address = server.local_addr();
+        server.spawn();
+
+        let (status, blocks) = http_get(address, "/blocks");
+        assert_eq!(status, 200);
+        assert_eq!(blocks[0]["hash"], genesis_hash);
+        assert_eq!(blocks[0]["n_txs"], 3);
+        assert_fields(&blocks[0], &["number", "hash", "n_txs", "volume"]);
+
+        let (status, block) = http_get(address, "/block/0");
+        assert_eq!(status, 200);
+        assert_eq!(block["txs"].as_array().unwrap().len(), 3);
+        assert_fields(
+            &block,
+            &[
+                "hash", "number", "root", "time", "n_txs", "mined", "reward", "diff",
+            ],
+        );
+        assert_fields(&block["txs"][0], &["hash", "fr", "to", "value"]);
+
+        let (status, tx) = http_get(address, &format!("/tx/0/{}", tx_hash));
+        assert_eq!(status, 200);
+        assert_fields(
+            &tx,
+            &["hash", "signed", "time", "fr", "to", "value", "nonce"],
+        );
+        assert_eq!(tx["fr"], ALLOCATION_SENDER.to_string());
+        assert_eq!(tx["to"], wallet.address().to_string());
+
+        let (status, acc) = http_get(address, &format!("/acc/{}", wallet.address()));
+        assert_eq!(status, 200);
+        assert_eq!(acc["nonce"], 0);
+
+        let (status, _) = http_get(address, &format!("/acc/{}", Address::named("unknown.eth")));
+        assert_eq!(status, 404);
+        let (status, _) = http_get(address, "/acc/unknown.eth");
+        assert_eq!(status, 400);
+        let (status, _) = http_get(address, &format!("/tx/{}", header_hash));
+        assert_eq!(status, 404);
+    }
+
+    #[test]
+    fn test_server_accepts_submitted_txs() {
+        let (blockchain, mut wallet) = get_test_blockchain();
+        let mempool = Arc::new(RwLock::new(Mempool::new()));
+        let server = ApiServer::bind(
+            "127.0.0.1:0",
+            Arc::new(RwLock::new(blockchain)),
+            mempool.clone(),
+        )
+        .unwrap();
+        let address = server.local_addr();
+        server.spawn();
+
+        let tx = wallet.send(Address::named("adel.eth"), BigDecimal::from(10));
+        let body = serde_json::to_string(&tx).unwrap();
+        let (status, submitted) = http_request(address, "POST", "/tx", &body);
+        assert_eq!(status, 200);
+        assert_eq!(submitted["hash"], tx.get_hash());
+        assert!(mempool.read().unwrap().get(&tx.get_hash()).is_some());
+
+        let (status, pool) = http_get(address, "/txs_pool");
+        assert_eq!(status, 200);
+        assert_eq!(pool[0]["hash"], tx.get_hash());
+        assert_fields(&pool[0], &["hash", "fr", "to", "value"]);
+        let (status, pending) = http_get(address, &format!("/tx_pool/{}", tx.get_hash()));
+        assert_eq!(status, 200);
+        assert_fields(
+            &pending,
+            &["hash", "signed", "time", "fr", "to", "value", "nonce"],
+        );
+
+        let (status, _) = http_request(address, "POST", "/tx", &body);
+        assert_eq!(status, 422);
+        let tx = wallet.send(Address::named("adel.eth"), BigDecimal::from(10));
+        let mut json = serde_json::to_value(&tx).unwrap();
+        json["to"] = tx.to.to_string().to_lowercase().into();
+        let (status, _) = http_request(address, "POST", "/tx", &json.to_string());
+        assert_eq!(status, 400);
+        let (status, _) = http_request(address, "POST", "/tx", "{}");
+        assert_eq!(status, 400);
+    }
+
+    #[test]
+    fn test_submitted_txs_reach_peers() {
+        let (blockchain, mut wallet) = get_test_blockchain();
+        let genesis = blockchain.get_last_block().clone();
+        let blockchain = Arc::new(RwLock::new(blockchain));
+        let mempool = Arc::new(RwLock::new(Mempool::new()));
+        let node = Node::bind("127.0.0.1:0", blockchain.clone(), mempool.clone()).unwrap();
+        node.spawn();
+        let peer_mempool = Arc::new(RwLock::new(Mempool::new()));
+        let peer = Node::bind(
+            "127.0.0.1:0",
+            Arc::new(RwLock::new(
+                Blockchain::from_genesis_block(genesis).unwrap(),
+            )),
+            peer_mempool.clone(),
+        )
+        .unwrap();
+        peer.connect(node.local_addr()).unwrap();
+
+        let mut server = ApiServer::bind("127.0.0.1:0", blockchain, mempool).unwrap();
+        server.node = Some(node.clone());
+        let address = server.local_addr();
+        server.spawn();
+        let start = Instant::now();
+        while node.peers_count() == 0 && start.elapsed() < Duration::from_secs(10) {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let tx = wallet.send(Address::named("adel.eth"), BigDecimal::from(10));
+        let body = serde_json::to_string(&tx).unwrap();
+        let (status, _) = http_request(address, "POST", "/tx", &body);
+        assert_eq!(status, 200);
+        let start = Instant::now();
+        while peer_mempool.read().unwrap().is_empty() && start.elapsed() < Duration::from_secs(10) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(peer_mempool.read().unwrap().get(&tx.get_hash()).is_some());
+    }
+}
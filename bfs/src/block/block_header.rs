@@ -2,10 +2,13 @@ use core::fmt;
 
 use bigdecimal::BigDecimal;
 use chrono::Utc;
+use num_bigint::BigUint;
 use serde::{Deserialize, Serialize};
 
+use crate::address::Address;
 use crate::hashable::Hashable;
-use crate::utils::{check_prefix, to_readable_hash};
+use crate::pow::{get_work, hash_meets_target};
+use crate::utils::to_readable_hash;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MiningBlockHeader {
@@ -15,9 +18,9 @@ pub struct MiningBlockHeader {
     pub txs_number: u64,
     pub mined: bool,
     pub created_at: i64,
-    pub difficulty: u64,
+    pub bits: u32,
     pub reward: BigDecimal,
-    pub miner_address: String,
+    pub miner_address: Address,
     pub nonce: u64,
 }
 
@@ -29,9 +32,9 @@ impl MiningBlockHeader {
         prev_hash: &str,
         block_number: u64,
         txs_number: u64,
-        difficulty: u64,
+        bits: u32,
         reward: BigDecimal,
-        miner_address: String,
+        miner_address: Address,
     ) -> MiningBlockHeader {
         MiningBlockHeader {
             hash: hash.to_string(),
@@ -40,7 +43,7 @@ impl MiningBlockHeader {
             txs_number,
             mined: true,
             created_at: Utc::now().timestamp(),
-            difficulty,
+            bits,
             reward,
             miner_address,
             nonce: 0,
@@ -52,7 +55,17 @@ impl MiningBlockHeader {
         let mut nonce_as_bytes = self.nonce.to_string().as_bytes().to_vec();
         block_header_bytes.append(&mut nonce_as_bytes);
         let candidate_hash = sha256::digest(&block_header_bytes);
-        check_prefix(&candidate_hash, '0', self.difficulty as usize)
+        hash_meets_target(&candidate_hash, self.bits)
+    }
+
+    /// Hash of the block this header has been mined for.
+    pub fn get_block_hash(&self) -> String {
+        BlockHeader::from(self).get_hash()
+    }
+
+    /// Expected number of hashes needed to find a valid nonce.
+    pub fn get_work(&self) -> BigUint {
+        get_work(self.bits)
     }
 }
 
@@ -65,10 +78,33 @@ impl From<&MiningBlockHeader> for BlockHeader {
             txs_number: mined_block.txs_number,
             created_at: mined_block.created_at,
             mined: mined_block.mined,
+            bits: mined_block.bits,
+            reward: mined_block.reward.clone(),
+            miner_address: mined_block.miner_address,
+            nonce: mined_block.nonce,
+        }
+    }
+}
+
+impl From<&BlockHeader> for MiningBlockHeader {
+    fn from(block_header: &BlockHeader) -> MiningBlockHeader {
+        MiningBlockHeader {
+            hash: block_header.hash.to_string(),
+            prev_hash: block_header.prev_hash.to_string(),
+            block_number: block_header.block_number,
+            txs_number: block_header.txs_number,
+            mined: block_header.mined,
+            created_at: block_header.created_at,
+            bits: block_header.bits,
+            reward: block_header.reward.clone(),
+            miner_address: block_header.miner_address,
+            nonce: block_header.nonce,
         }
     }
 }
 
+/// Header of a stored block. It carries every consensus field of the mined
+/// header, so the proof of work can be checked from the block alone.
 #[derive(Clone, Serialize, Deserialize)]
 pub struct BlockHeader {
     pub hash: String,
@@ -77,6 +113,10 @@ pub struct BlockHeader {
     pub txs_number: u64,
     pub mined: bool,
     pub created_at: i64,
+    pub bits: u32,
+    pub reward: BigDecimal,
+    pub miner_address: Address,
+    pub nonce: u64,
 }
 
 impl BlockHeader {
@@ -88,8 +128,21 @@ impl BlockHeader {
             txs_number,
             mined: false,
             created_at: Utc::now().timestamp(),
+            bits: 0,
+            reward: BigDecimal::from(0),
+            miner_address: Address::default(),
+            nonce: 0,
         }
     }
+
+    pub fn is_pow_valid(&self) -> bool {
+        MiningBlockHeader::from(self).is_pow_computation_valid()
+    }
+
+    /// Expected number of hashes needed to find a valid nonce.
+    pub fn get_work(&self) -> BigUint {
+        get_work(self.bits)
+    }
 }
 
 impl Hashable for BlockHeader {}
@@ -104,13 +157,21 @@ impl fmt::Display for BlockHeader {
         txs_number:                     {}
         mined:                          {}
         created_at:                     {}
+        bits:                           {:#010x}
+        reward:                         {}
+        miner_address:                  {}
+        nonce:                          {}
         "#,
             to_readable_hash(&self.hash),
             to_readable_hash(&self.prev_hash),
             self.block_number,
             self.txs_number,
             self.mined,
-            self.created_at
+            self.created_at,
+            self.bits,
+            self.reward,
+            self.miner_address,
+            self.nonce
         )
     }
 }
@@ -130,10 +191,36 @@ mod tests {
             txs_number: 42,
             mined: false,
             created_at: Utc::now().timestamp(),
+            bits: 0,
+            reward: BigDecimal::from(0),
+            miner_address: Address::default(),
+            nonce: 0,
         };
         let first_hash = block.get_hash();
         block.txs_number = 45;
         let new_hash = block.get_hash();
         assert_ne!(first_hash, new_hash);
+        block.nonce = 1;
+        assert_ne!(block.get_hash(), new_hash);
+    }
+
+    #[test]
+    fn test_mined_header_round_trip() {
+        let mut header = MiningBlockHeader::new(
+            "hash",
+            "prev_hash",
+            1,
+            1,
+            0x207fffff,
+            BigDecimal::from(50),
+            Address::named("miner"),
+        );
+        header.nonce = 42;
+        let block_header = BlockHeader::from(&header);
+        assert_eq!(block_header.get_hash(), header.get_block_hash());
+        assert_eq!(
+            MiningBlockHeader::from(&block_header).get_hash(),
+            header.get_hash()
+        );
     }
 }
@@ -4,20 +4,43 @@ pub mod block_info;
 pub use block_header::BlockHeader;
 pub use block_info::BlockInfo;
 
+use chrono::Utc;
 use core::fmt;
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 
 use crate::hashable::Hashable;
 use crate::merkle_tree::MerkleTree;
+use crate::miner::MiningError;
 use crate::transaction::Transaction;
 use crate::utils::to_readable_hash;
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum BlockError {
     InvalidTransaction,
     DuplicatedTransaction,
     InvalidBlockHeader,
+    InvalidMerkleRoot,
+    InvalidBlockHash,
+    DuplicatedBlock,
+    InvalidGenesis,
+    InvalidSignature,
+    WrongChain { expected: u64, got: u64 },
+    NonceMismatch { expected: u64, got: u64 },
+    InsufficientBalance,
+    NegativeAmount,
+    UnauthorizedCoinbase,
+    MissingCoinbase,
+    MultipleCoinbases,
+    InvalidCoinbase,
+    InvalidMinedHeader(MiningError),
+    StorageFailure,
+}
+
+impl From<MiningError> for BlockError {
+    fn from(err: MiningError) -> BlockError {
+        BlockError::InvalidMinedHeader(err)
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -34,13 +57,15 @@ impl Hashable for Block {}
 pub fn validate_and_get_transactions(
     merkle_tree: &MerkleTree,
     txs: &[Transaction],
+    block_number: u64,
 ) -> Result<IndexMap<String, Transaction>, BlockError> {
+    check_coinbase(txs, block_number)?;
     let mut transactions = IndexMap::new();
     for tx in txs {
         if !merkle_tree.tx_is_in(tx) {
             return Err(BlockError::InvalidTransaction);
         }
-        if !tx.is_correctly_signed() {
+        if !is_authorized(tx, block_number) {
             return Err(BlockError::InvalidTransaction);
         }
         if transactions.insert(tx.get_hash(), tx.clone()).is_some() {
@@ -50,11 +75,40 @@ pub fn validate_and_get_transactions(
     Ok(transactions)
 }
 
+/// Transactions must be signed, but for the allocations of the genesis block
+/// and the coinbase of the other ones. Their chain is checked once applied to
+/// the state, as a block alone doesn't know which network it belongs to.
+fn is_authorized(tx: &Transaction, block_number: u64) -> bool {
+    match block_number {
+        0 => tx.is_signature_valid() || tx.is_allocation(),
+        _ => tx.is_signature_valid() || tx.is_coinbase(),
+    }
+}
+
+/// Every block but the genesis one starts with a coinbase, its only one.
+/// Its nonce is the block number, so coinbases of different blocks never
+/// share a hash.
+fn check_coinbase(txs: &[Transaction], block_number: u64) -> Result<(), BlockError> {
+    if block_number == 0 {
+        return Ok(());
+    }
+    if !txs.first().is_some_and(|tx| tx.is_coinbase()) {
+        return Err(BlockError::MissingCoinbase);
+    }
+    if txs[1..].iter().any(|tx| tx.is_coinbase()) {
+        return Err(BlockError::MultipleCoinbases);
+    }
+    if txs[0].nonce != block_number {
+        return Err(BlockError::InvalidCoinbase);
+    }
+    Ok(())
+}
+
 impl Block {
     pub fn new(block_header: BlockHeader, txs: &[Transaction]) -> Result<Block, BlockError> {
         let merkle_tree = MerkleTree::new(txs);
         let block = Block {
-            txs: validate_and_get_transactions(&merkle_tree, txs)?,
+            txs: validate_and_get_transactions(&merkle_tree, txs, block_header.block_number)?,
             merkle_tree,
             block_hash: block_header.get_hash(),
             block_header,
@@ -66,6 +120,56 @@ impl Block {
         Ok(block)
     }
 
+    pub fn genesis(txs: &[Transaction]) -> Result<Block, BlockError> {
+        Block::genesis_at(txs, Utc::now().timestamp())
+    }
+
+    /// Genesis block created at a fixed time, whose hash then only depends
+    /// on its transactions.
+    pub fn genesis_at(txs: &[Transaction], created_at: i64) -> Result<Block, BlockError> {
+        let merkle_tree = MerkleTree::new(txs);
+        let mut block_header = BlockHeader::new(merkle_tree.get_root(), "0", 0, txs.len() as u64);
+        block_header.created_at = created_at;
+        Block::new(block_header, txs)
+    }
+
+    /// Checks that the block is consistent with itself: transactions match
+    /// their hash & signature, the Merkle root matches the transactions, the
+    /// header holds a valid proof of work (but for the genesis block) and the
+    /// block hash matches the header.
+    pub fn verify(&self) -> Result<(), BlockError> {
+        let txs: Vec<Transaction> = self.txs.values().cloned().collect();
+        if txs.is_empty() || self.block_header.txs_number != txs.len() as u64 {
+            return Err(BlockError::InvalidBlockHeader);
+        }
+        check_coinbase(&txs, self.block_header.block_number)?;
+        for (tx_hash, tx) in self.txs.iter() {
+            if &tx.get_hash() != tx_hash {
+                return Err(BlockError::InvalidTransaction);
+            }
+            if !is_authorized(tx, self.block_header.block_number) {
+                return Err(BlockError::InvalidSignature);
+            }
+        }
+        let merkle_tree = MerkleTree::new(&txs);
+        if merkle_tree.get_root() != self.block_header.hash || merkle_tree != self.merkle_tree {
+            return Err(BlockError::InvalidMerkleRoot);
+        }
+        let block_info = BlockInfo::new(&txs);
+        if block_info.volume != self.block_info.volume
+            || block_info.total_fees != self.block_info.total_fees
+        {
+            return Err(BlockError::InvalidBlockHeader);
+        }
+        if self.block_header.block_number > 0 && !self.block_header.is_pow_valid() {
+            return Err(MiningError::InvalidProofOfWork.into());
+        }
+        if self.block_header.get_hash() != self.block_hash {
+            return Err(BlockError::InvalidBlockHash);
+        }
+        Ok(())
+    }
+
     pub fn get_tx(&self, tx_hash: &str) -> Option<&Transaction> {
         self.txs.get(tx_hash)
     }
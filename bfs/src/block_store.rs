@@ -0,0 +1,305 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::block::{Block, BlockError};
+use crate::hashable::Hashable;
+
+pub const BLOCK_STORE_FILE: &str = "blocks.dat";
+
+// Every record is `[payload length: u64 LE][sha256 of payload: 64 hex bytes][payload]`.
+const RECORD_LEN_SIZE: u64 = 8;
+const RECORD_CHECKSUM_SIZE: u64 = 64;
+const RECORD_HEADER_SIZE: u64 = RECORD_LEN_SIZE + RECORD_CHECKSUM_SIZE;
+
+#[derive(Debug)]
+pub enum StoreError {
+    Io(io::Error),
+    AlreadyExists,
+    MissingGenesis,
+    CorruptedRecord,
+    UndecodableRecord,
+    InvalidBlock(BlockError),
+}
+
+impl From<io::Error> for StoreError {
+    fn from(err: io::Error) -> StoreError {
+        StoreError::Io(err)
+    }
+}
+
+impl From<BlockError> for StoreError {
+    fn from(err: BlockError) -> StoreError {
+        StoreError::InvalidBlock(err)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct BlockLocation {
+    offset: u64,
+    len: u64,
+}
+
+/// Append-only log of every block accepted by a `Blockchain`, competing
+/// branches included, read back in order when the chain is replayed.
+///
+/// Records are checksummed and synced one by one: a record partially written
+/// during a crash is detected and truncated the next time the store is opened.
+pub struct BlockStore {
+    path: PathBuf,
+    file: File,
+    len: u64,
+    locations: Vec<BlockLocation>,
+}
+
+impl BlockStore {
+    /// Creates an empty store in `dir`, failing if one already exists.
+    pub fn create<P: AsRef<Path>>(dir: P) -> Result<BlockStore, StoreError> {
+        let path = dir.as_ref().join(BLOCK_STORE_FILE);
+        if path.exists() {
+            return Err(StoreError::AlreadyExists);
+        }
+        fs::create_dir_all(dir.as_ref())?;
+        BlockStore::open(dir)
+    }
+
+    /// Opens the store in `dir` and rebuilds its indexes.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<BlockStore, StoreError> {
+        let path = dir.as_ref().join(BLOCK_STORE_FILE);
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+        let mut store = BlockStore {
+            path,
+            file,
+            len: 0,
+            locations: Vec::new(),
+        };
+        store.load()?;
+        Ok(store)
+    }
+
+    pub fn exists<P: AsRef<Path>>(dir: P) -> bool {
+        dir.as_ref().join(BLOCK_STORE_FILE).exists()
+    }
+
+    fn load(&mut self) -> Result<(), StoreError> {
+        let file_len = self.file.metadata()?.len();
+        let mut offset = 0;
+        while offset < file_len {
+            let stored = match self.read_record(offset, file_len) {
+                Ok(Some(record)) => record,
+                // Torn write from a previous crash: drop the partial last record.
+                Ok(None) => {
+                    self.truncate(offset)?;
+                    break;
+                }
+                Err(StoreError::CorruptedRecord) if self.is_last_record(offset, file_len)? => {
+                    self.truncate(offset)?;
+                    break;
+                }
+                Err(err) => return Err(err),
+            };
+            let len = stored.1;
+            self.locations.push(BlockLocation { offset, len });
+            offset += RECORD_HEADER_SIZE + len;
+        }
+        self.len = offset;
+        Ok(())
+    }
+
+    fn is_last_record(&self, offset: u64, file_len: u64) -> io::Result<bool> {
+        let mut len = [0u8; RECORD_LEN_SIZE as usize];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut len)?;
+        Ok(record_end(offset, u64::from_le_bytes(len)) == Some(file_len))
+    }
+
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        self.file.set_len(len)?;
+        self.file.sync_all()
+    }
+
+    /// Reads the record at `offset`, `None` if it is cut by the end of file.
+    fn read_record(&self, offset: u64, file_len: u64) -> Result<Option<(Block, u64)>, StoreError> {
+        if offset + RECORD_HEADER_SIZE > file_len {
+            return Ok(None);
+        }
+        let mut header = [0u8; RECORD_HEADER_SIZE as usize];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut header)?;
+        let len = u64::from_le_bytes(header[..RECORD_LEN_SIZE as usize].try_into().unwrap());
+        // A length past the largest offset can only come from a corrupted record
+        let end = record_end(offset, len).ok_or(StoreError::CorruptedRecord)?;
+        if end > file_len {
+            return Ok(None);
+        }
+        let mut payload = vec![0u8; len as usize];
+        file.read_exact(&mut payload)?;
+        if sha256::digest(&payload).as_bytes() != &header[RECORD_LEN_SIZE as usize..] {
+            return Err(StoreError::CorruptedRecord);
+        }
+        let block = bincode::deserialize(&payload).map_err(|_| StoreError::UndecodableRecord)?;
+        Ok(Some((block, len)))
+    }
+
+    /// Appends a block and waits for it to reach the disk.
+    pub fn append(&mut self, block: &Block) -> Result<(), StoreError> {
+        let payload = block.to_bytes();
+        let len = payload.len() as u64;
+        let mut record = Vec::with_capacity((RECORD_HEADER_SIZE + len) as usize);
+        record.extend_from_slice(&len.to_le_bytes());
+        record.extend_from_slice(sha256::digest(&payload).as_bytes());
+        record.extend_from_slice(&payload);
+        if let Err(err) = self
+            .file
+            .write_all(&record)
+            .and_then(|_| self.file.sync_data())
+        {
+            // Don't leave a partial record for the next append to follow.
+            let _ = self.file.set_len(self.len);
+            return Err(err.into());
+        }
+        let location = BlockLocation {
+            offset: self.len,
+            len,
+        };
+        self.len += RECORD_HEADER_SIZE + len;
+        self.locations.push(location);
+        Ok(())
+    }
+
+    fn read_at(&self, location: BlockLocation) -> Result<Block, StoreError> {
+        let end = location.offset + RECORD_HEADER_SIZE + location.len;
+        match self.read_record(location.offset, end)? {
+            Some((block, _)) => Ok(block),
+            None => Err(StoreError::CorruptedRecord),
+        }
+    }
+
+    /// Iterates over the stored blocks in the order they were appended.
+    pub fn iter(&self) -> impl Iterator<Item = Result<Block, StoreError>> + '_ {
+        self.locations
+            .iter()
+            .map(|location| self.read_at(*location))
+    }
+
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Makes every following append fail, as a full disk would.
+    #[cfg(test)]
+    pub(crate) fn make_read_only(&mut self) {
+        self.file = File::open(&self.path).unwrap();
+    }
+}
+
+/// Offset following the record at `offset` holding a `len` bytes payload.
+fn record_end(offset: u64, len: u64) -> Option<u64> {
+    offset.checked_add(RECORD_HEADER_SIZE)?.checked_add(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::transaction::Transaction;
+    use crate::wallet::Wallet;
+
+    fn get_test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("bfs-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_append_and_reopen() {
+        let dir = get_test_dir("store-reopen");
+        let wallet = Wallet::new();
+        let genesis =
+            Block::genesis(&[Transaction::allocation(wallet.address(), 100_000.into(), 0)])
+                .unwrap();
+
+        let mut store = BlockStore::create(&dir).unwrap();
+        store.append(&genesis).unwrap();
+        assert!(matches!(
+            BlockStore::create(&dir),
+            Err(StoreError::AlreadyExists)
+        ));
+        drop(store);
+
+        let store = BlockStore::open(&dir).unwrap();
+        assert_eq!(store.len(), 1);
+        let stored = store.iter().next().unwrap().unwrap();
+        assert_eq!(stored.block_header.get_hash(), genesis.block_hash);
+        assert!(stored.merkle_tree == genesis.merkle_tree);
+        assert_eq!(
+            stored.txs.keys().collect::<Vec<_>>(),
+            genesis.txs.keys().collect::<Vec<_>>()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_torn_write_is_truncated() {
+        let dir = get_test_dir("store-torn");
+        let wallet = Wallet::new();
+        let genesis =
+            Block::genesis(&[Transaction::allocation(wallet.address(), 100_000.into(), 0)])
+                .unwrap();
+        let mut store = BlockStore::create(&dir).unwrap();
+        store.append(&genesis).unwrap();
+        let valid_len = fs::metadata(store.path()).unwrap().len();
+        drop(store);
+
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(dir.join(BLOCK_STORE_FILE))
+            .unwrap();
+        file.write_all(&[42u8; 30]).unwrap();
+        drop(file);
+
+        let mut store = BlockStore::open(&dir).unwrap();
+        assert_eq!(store.len(), 1);
+        assert_eq!(fs::metadata(store.path()).unwrap().len(), valid_len);
+        store.append(&genesis).unwrap();
+        assert_eq!(BlockStore::open(&dir).unwrap().len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_huge_record_length_is_corrupted() {
+        let dir = get_test_dir("store-huge-len");
+        let wallet = Wallet::new();
+        let genesis =
+            Block::genesis(&[Transaction::allocation(wallet.address(), 100_000.into(), 0)])
+                .unwrap();
+        let mut store = BlockStore::create(&dir).unwrap();
+        store.append(&genesis).unwrap();
+        store.append(&genesis).unwrap();
+        drop(store);
+
+        let mut content = fs::read(dir.join(BLOCK_STORE_FILE)).unwrap();
+        content[..RECORD_LEN_SIZE as usize].copy_from_slice(&u64::MAX.to_le_bytes());
+        fs::write(dir.join(BLOCK_STORE_FILE), content).unwrap();
+        assert!(matches!(
+            BlockStore::open(&dir),
+            Err(StoreError::CorruptedRecord)
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
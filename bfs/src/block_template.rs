@@ -0,0 +1,211 @@
+use std::collections::HashSet;
+
+use bigdecimal::BigDecimal;
+
+use crate::address::Address;
+use crate::block::block_header::MiningBlockHeader;
+use crate::blockchain::Blockchain;
+use crate::mempool::Mempool;
+use crate::merkle_tree::MerkleTree;
+use crate::transaction::Transaction;
+
+pub const MAX_BLOCK_TXS: usize = 1_000;
+pub const MAX_BLOCK_SIZE: usize = 1_000_000;
+
+/// Limits a block template must fit in, coinbase included.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockLimits {
+    pub max_txs: usize,
+    /// Size of the serialized transactions, in bytes.
+    pub max_size: usize,
+}
+
+impl Default for BlockLimits {
+    fn default() -> Self {
+        BlockLimits {
+            max_txs: MAX_BLOCK_TXS,
+            max_size: MAX_BLOCK_SIZE,
+        }
+    }
+}
+
+/// Transactions of the next block together with the header to mine.
+pub struct BlockTemplate {
+    pub header: MiningBlockHeader,
+    /// Coinbase first, then pool transactions in execution order.
+    pub txs: Vec<Transaction>,
+    pub total_fees: BigDecimal,
+    pub size: usize,
+}
+
+impl BlockTemplate {
+    /// Fills a template on top of the tip of the blockchain with the best
+    /// transactions of the pool, highest fee first, behind a coinbase paying
+    /// `miner_address` the reward and the fees they leave.
+    ///
+    /// Every transaction is applied to a copy of the state: one that would be
+    /// rejected, or that does not fit, is skipped along with the following
+    /// transactions of its sender, whose nonces would then leave a gap.
+    pub fn build(
+        blockchain: &Blockchain,
+        mempool: &Mempool,
+        miner_address: &Address,
+        limits: &BlockLimits,
+    ) -> BlockTemplate {
+        let last_block = blockchain.get_last_block();
+        let block_number = last_block.block_header.block_number + 1;
+        // The coinbase grows along with the fees it collects
+        let get_coinbase = |fees: &BigDecimal| {
+            let burned_fees = blockchain.params.get_burned_fees(fees);
+            let value = &blockchain.mining_reward + fees - burned_fees;
+            Transaction {
+                chain_id: blockchain.params.chain_id,
+                ..Transaction::coinbase(*miner_address, value, block_number)
+            }
+        };
+        let mut state = blockchain.state.clone();
+        let mut size = 0;
+        let mut total_fees = BigDecimal::from(0);
+        let mut txs = Vec::new();
+        let mut skipped_senders = HashSet::new();
+        for tx in mempool.select(&blockchain.state, usize::MAX) {
+            if txs.len() + 1 >= limits.max_txs {
+                break;
+            }
+            if skipped_senders.contains(&tx.from) {
+                continue;
+            }
+            let tx_size = get_tx_size(&tx);
+            let coinbase_size = get_tx_size(&get_coinbase(&(&total_fees + &tx.fee)));
+            if size + tx_size + coinbase_size > limits.max_size || state.apply_tx(&tx).is_err() {
+                skipped_senders.insert(tx.from);
+                continue;
+            }
+            size += tx_size;
+            total_fees += &tx.fee;
+            txs.push(tx);
+        }
+        let coinbase = get_coinbase(&total_fees);
+        size += get_tx_size(&coinbase);
+        txs.insert(0, coinbase);
+
+        let header = MiningBlockHeader::new(
+            &MerkleTree::new(&txs).get_root(),
+            &last_block.block_hash,
+            block_number,
+            txs.len() as u64,
+            blockchain.mining_bits,
+            blockchain.mining_reward.clone(),
+            *miner_address,
+        );
+        BlockTemplate {
+            header,
+            txs,
+            total_fees,
+            size,
+        }
+    }
+}
+
+fn get_tx_size(tx: &Transaction) -> usize {
+    bincode::serialized_size(tx).unwrap() as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use crate::hashable::Hashable;
+    use crate::miner::Miner;
+    use crate::wallet::Wallet;
+
+    fn get_test_blockchain(wallets: &[&Wallet]) -> Blockchain {
+        let txs: Vec<Transaction> = wallets
+            .iter()
+            .map(|wallet| Transaction::allocation(wallet.address(), 100_000.into(), 0))
+            .collect();
+        Blockchain::from_genesis_block(Block::genesis(&txs).unwrap()).unwrap()
+    }
+
+    fn send(wallet: &mut Wallet, fee: u64) -> Transaction {
+        let tx = Transaction::new(
+            wallet.address(),
+            Address::named("adel.eth"),
+            BigDecimal::from(1),
+            BigDecimal::from(fee),
+            wallet.nonce,
+        );
+        wallet.nonce += 1;
+        wallet.sign(tx)
+    }
+
+    #[test]
+    fn test_template_is_mined_and_accepted() {
+        let mut wallet_a = Wallet::new();
+        let mut wallet_b = Wallet::new();
+        let mut blockchain = get_test_blockchain(&[&wallet_a, &wallet_b]);
+        let mut mempool = Mempool::new();
+        for tx in [send(&mut wallet_a, 1), send(&mut wallet_a, 2)] {
+            mempool.add(tx, &blockchain.state).unwrap();
+        }
+        mempool
+            .add(send(&mut wallet_b, 5), &blockchain.state)
+            .unwrap();
+
+        let miner = Miner::new(Wallet::new());
+        let limits = BlockLimits {
+            max_txs: 3,
+            ..BlockLimits::default()
+        };
+        let template = miner.build_template(&blockchain, &mempool, &limits);
+        assert_eq!(template.txs.len(), 3);
+        assert_eq!(template.txs[1].from, wallet_b.address());
+        assert_eq!(template.txs[2].nonce, 0);
+        assert_eq!(template.total_fees, BigDecimal::from(6));
+        assert!(template.txs[0].is_coinbase());
+        assert_eq!(
+            template.txs[0].value,
+            &blockchain.mining_reward + &template.total_fees
+        );
+
+        let header = miner
+            .start_template_session(&template)
+            .mine(u64::MAX, |_| {})
+            .unwrap();
+        let block = blockchain
+            .build_block_candidate(&header, &template.txs)
+            .unwrap();
+        assert!(blockchain.add_block(&block).is_ok());
+        assert_eq!(block.block_info.total_fees, template.total_fees);
+        assert_eq!(
+            blockchain.state.get_total_supply(),
+            blockchain.get_issued_supply()
+        );
+    }
+
+    #[test]
+    fn test_sender_is_skipped_after_a_tx_that_does_not_fit() {
+        let mut wallet_a = Wallet::new();
+        let mut wallet_b = Wallet::new();
+        let blockchain = get_test_blockchain(&[&wallet_a, &wallet_b]);
+        let mut mempool = Mempool::new();
+        let a1 = send(&mut wallet_a, 10);
+        let a2 = send(&mut wallet_a, 10);
+        let b1 = send(&mut wallet_b, 1);
+        for tx in [a1.clone(), a2, b1.clone()] {
+            mempool.add(tx, &blockchain.state).unwrap();
+        }
+
+        let miner = Miner::new(Wallet::new());
+        let coinbase = Transaction::coinbase(miner.get_address(), BigDecimal::from(12), 1);
+        // Room for the coinbase and 2 transactions: a2 doesn't fit, b1 still does
+        let limits = BlockLimits {
+            max_txs: MAX_BLOCK_TXS,
+            max_size: get_tx_size(&coinbase) + get_tx_size(&a1) + get_tx_size(&b1),
+        };
+        let template = BlockTemplate::build(&blockchain, &mempool, &miner.get_address(), &limits);
+        let hashes: Vec<String> = template.txs[1..].iter().map(|tx| tx.get_hash()).collect();
+        assert_eq!(hashes, vec![a1.get_hash(), b1.get_hash()]);
+        assert!(template.size <= limits.max_size);
+    }
+}
@@ -1,66 +1,1023 @@
-use bigdecimal::BigDecimal;
+use std::collections::HashMap;
+use std::path::Path;
+
+use bigdecimal::{BigDecimal, Zero};
+use chrono::Utc;
+use num_bigint::BigUint;
 use serde::{Deserialize, Serialize};
 
+use crate::address::Address;
 use crate::block::block_header::MiningBlockHeader;
-use crate::block::{Block, BlockHeader};
+use crate::block::{Block, BlockError, BlockHeader};
+use crate::block_store::{BlockStore, StoreError};
+use crate::consensus::{ConsensusParams, ParamsError, MAX_FUTURE_BLOCK_TIME};
 use crate::hashable::Hashable;
-use crate::state::State;
+use crate::miner::MiningError;
+use crate::state::{State, StateJournal};
 use crate::transaction::Transaction;
 
-pub const BASE_MINING_DIFFICULTY: u64 = 3;
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+    EmptyChain,
+    /// The file is a chain of another network.
+    WrongGenesis {
+        expected: String,
+        got: String,
+    },
+    /// The expected consensus rules are not valid ones.
+    InvalidParams(ParamsError),
+    /// The file claims other consensus rules than the expected ones.
+    ParamsMismatch,
+    InvalidBlock {
+        block_number: u64,
+        error: BlockError,
+    },
+    StateMismatch {
+        address: Address,
+    },
+}
+
+impl From<std::io::Error> for LoadError {
+    fn from(err: std::io::Error) -> LoadError {
+        LoadError::Io(err)
+    }
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct Blockchain {
     pub state: State,
     pub blocks: Vec<Block>,
-    pub mining_difficulty: u64,
+    pub mining_bits: u32,
     pub mining_reward: BigDecimal,
+    #[serde(default)]
+    pub params: ConsensusParams,
+    /// Undo log of every block but the genesis one.
+    #[serde(default)]
+    pub journals: Vec<StateJournal>,
+    /// Valid blocks of the competing branches, by block hash.
+    #[serde(default)]
+    pub side_blocks: HashMap<String, Block>,
+    /// On-disk store every accepted block gets appended to, if any.
+    #[serde(skip)]
+    store: Option<BlockStore>,
+    /// Transfers of the blocks the last `add_block` removed from the active
+    /// chain, for the mempool to take back.
+    #[serde(skip)]
+    disconnected_txs: Vec<Transaction>,
 }
 
 impl Blockchain {
-    pub fn from_genesis_block(genesis_block: Block) -> Blockchain {
-        Blockchain {
-            state: State::from_genesis(&genesis_block),
+    pub fn from_genesis_block(genesis_block: Block) -> Result<Blockchain, BlockError> {
+        Blockchain::new(genesis_block, ConsensusParams::default())
+    }
+
+    pub fn new(genesis_block: Block, params: ConsensusParams) -> Result<Blockchain, BlockError> {
+        let genesis_hash = genesis_block.block_hash.clone();
+        let mut state = State::from_genesis(&genesis_block)?;
+        state.chain_id = params.chain_id;
+        let mut blockchain = Blockchain {
+            state,
             blocks: vec![genesis_block],
-            mining_difficulty: BASE_MINING_DIFFICULTY,
-            mining_reward: BigDecimal::from(1),
+            mining_bits: params.initial_bits,
+            mining_reward: BigDecimal::zero(),
+            params,
+            journals: Vec::new(),
+            side_blocks: HashMap::new(),
+            store: None,
+            disconnected_txs: Vec::new(),
+        };
+        blockchain.mining_reward = blockchain.get_next_reward(&genesis_hash).unwrap();
+        Ok(blockchain)
+    }
+
+    /// Creates a new block store in `dir` starting with the genesis block.
+    pub fn create<P: AsRef<Path>>(
+        dir: P,
+        genesis_block: Block,
+        params: ConsensusParams,
+    ) -> Result<Blockchain, StoreError> {
+        let mut blockchain = Blockchain::new(genesis_block, params)?;
+        let mut store = BlockStore::create(dir)?;
+        store.append(blockchain.get_last_block())?;
+        blockchain.store = Some(store);
+        Ok(blockchain)
+    }
+
+    /// Rebuilds the blockchain by replaying every block of the store in `dir`.
+    pub fn open<P: AsRef<Path>>(dir: P, params: ConsensusParams) -> Result<Blockchain, StoreError> {
+        let store = BlockStore::open(dir)?;
+        let mut stored_blocks = store.iter();
+        let genesis = match stored_blocks.next() {
+            Some(block) => block?,
+            None => return Err(StoreError::MissingGenesis),
+        };
+        let mut blockchain = Blockchain::new(genesis, params)?;
+        for block in stored_blocks {
+            blockchain.add_block(&block?)?;
         }
+        blockchain.store = Some(store);
+        Ok(blockchain)
     }
 
     pub fn build_block_candidate(
         &self,
         header_mined: &MiningBlockHeader,
         txs: &[Transaction],
-    ) -> Block {
+    ) -> Result<Block, BlockError> {
         let block_header = BlockHeader::from(header_mined);
-        Block::new(block_header, txs).unwrap()
+        Block::new(block_header, txs)
+    }
+
+    /// Loads a blockchain from a JSON file, only trusting it once it has been
+    /// replayed from the genesis block (see `Blockchain::replay`).
+    pub fn load_json_file(
+        file_path: &str,
+        genesis_hash: &str,
+        params: &ConsensusParams,
+    ) -> Result<Blockchain, LoadError> {
+        let blockchain: Blockchain = Blockchain::from_json_file(file_path)?;
+        blockchain.replay(genesis_hash, params)
+    }
+
+    /// Rebuilds the blockchain from its blocks alone, checking every block
+    /// (Merkle root, block hash, prev hash, PoW, signatures, nonces & balances)
+    /// and that the recomputed state matches the stored one.
+    ///
+    /// The network is the caller's one: the chain must start at `genesis_hash`
+    /// and follow `params`, whatever it claims.
+    ///
+    /// Returns the rebuilt blockchain, or the first inconsistency found.
+    pub fn replay(
+        &self,
+        genesis_hash: &str,
+        params: &ConsensusParams,
+    ) -> Result<Blockchain, LoadError> {
+        let invalid_block = |block_number: u64| {
+            move |error: BlockError| LoadError::InvalidBlock {
+                block_number,
+                error,
+            }
+        };
+        let genesis = self.blocks.first().ok_or(LoadError::EmptyChain)?;
+        if genesis.block_hash != genesis_hash {
+            return Err(LoadError::WrongGenesis {
+                expected: genesis_hash.to_string(),
+                got: genesis.block_hash.clone(),
+            });
+        }
+        params.validate().map_err(LoadError::InvalidParams)?;
+        if self.params != *params {
+            return Err(LoadError::ParamsMismatch);
+        }
+        genesis.verify().map_err(invalid_block(0))?;
+        let mut replayed =
+            Blockchain::new(genesis.clone(), params.clone()).map_err(invalid_block(0))?;
+
+        for (i, block) in self.blocks.iter().enumerate().skip(1) {
+            let block_number = i as u64;
+            if block.block_header.block_number != block_number {
+                return Err(invalid_block(block_number)(BlockError::InvalidBlockHeader));
+            }
+            replayed
+                .add_block(block)
+                .map_err(invalid_block(block_number))?;
+        }
+
+        for (address, account) in self.state.state.iter() {
+            if replayed.state.state.get(address) != Some(account) {
+                return Err(LoadError::StateMismatch { address: *address });
+            }
+        }
+        if let Some(address) = replayed
+            .state
+            .state
+            .keys()
+            .find(|address| self.state.is_new(address))
+        {
+            return Err(LoadError::StateMismatch { address: *address });
+        }
+
+        let mut side_blocks: Vec<&Block> = self.side_blocks.values().collect();
+        side_blocks.sort_by_key(|block| block.block_header.block_number);
+        for block in side_blocks {
+            let block_number = block.block_header.block_number;
+            replayed
+                .add_block(block)
+                .map_err(invalid_block(block_number))?;
+        }
+        Ok(replayed)
+    }
+
+    /// Adds a mined block to the blockchain.
+    ///
+    /// Blocks extending the tip are applied right away. Blocks extending any
+    /// other known block are kept as a competing branch, and the chain is
+    /// reorganized as soon as that branch carries more work than the active one.
+    ///
+    /// Once accepted, the block is appended to the block store if there is one.
+    /// If that fails, the block is forgotten so memory and disk stay in sync.
+    pub fn add_block(&mut self, new_block: &Block) -> Result<(), BlockError> {
+        self.disconnected_txs.clear();
+        let tip_hash = self.get_last_block().block_hash.clone();
+        self.accept_block(new_block)?;
+        if let Some(store) = self.store.as_mut() {
+            if store.append(new_block).is_err() {
+                self.forget_block(&new_block.block_hash, &tip_hash);
+                return Err(BlockError::StorageFailure);
+            }
+        }
+        Ok(())
+    }
+
+    /// Undoes the acceptance of a block, `tip_hash` being the tip before it.
+    fn forget_block(&mut self, block_hash: &str, tip_hash: &str) {
+        if self.side_blocks.remove(block_hash).is_some() {
+            return;
+        }
+        // The block is the new tip, maybe after a reorganization
+        self.disconnect_tip();
+        if self.get_last_block().block_hash != tip_hash {
+            self.reorganize(tip_hash)
+                .expect("Previously active chain must still be valid");
+        }
+    }
+
+    fn accept_block(&mut self, new_block: &Block) -> Result<(), BlockError> {
+        new_block.verify()?;
+        let header = &new_block.block_header;
+        self.is_mined_block_valid(header)?;
+        if self.is_known_block(&new_block.block_hash) {
+            return Err(BlockError::DuplicatedBlock);
+        }
+        if header.prev_hash == self.get_last_block().block_hash {
+            return self.connect_block(new_block.clone());
+        }
+        let block_hash = new_block.block_hash.clone();
+        self.side_blocks
+            .insert(block_hash.clone(), new_block.clone());
+        if self.get_branch_work(&block_hash) > self.get_total_work() {
+            self.reorganize(&block_hash)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the last block and reverts its changes on the state.
+    /// The genesis block can't be reverted.
+    pub fn revert_last_block(&mut self) -> Option<Block> {
+        self.disconnect_tip()
+    }
+
+    fn connect_block(&mut self, block: Block) -> Result<(), BlockError> {
+        let header = &block.block_header;
+        let burned_fees = self.params.get_burned_fees(&block.block_info.total_fees);
+        let minted = self.get_minted_supply(&header.prev_hash) + &header.reward;
+        let mut journal =
+            self.state
+                .apply_block(&block, &header.miner_address, &header.reward, &burned_fees)?;
+        journal.minted = minted;
+        self.blocks.push(block);
+        self.journals.push(journal);
+        self.update_mining_params();
+        Ok(())
+    }
+
+    fn disconnect_tip(&mut self) -> Option<Block> {
+        if self.blocks.len() <= 1 {
+            return None;
+        }
+        let journal = self.journals.pop()?;
+        self.state.revert(journal);
+        let block = self.blocks.pop()?;
+        let txs = block.txs.values();
+        self.disconnected_txs
+            .extend(txs.filter(|tx| !tx.is_coinbase()).cloned());
+        self.update_mining_params();
+        Some(block)
+    }
+
+    /// Takes the transfers of the blocks the last `add_block` disconnected,
+    /// some of them may not be part of the new active chain.
+    pub fn take_disconnected_txs(&mut self) -> Vec<Transaction> {
+        std::mem::take(&mut self.disconnected_txs)
+    }
+
+    fn update_mining_params(&mut self) {
+        let tip_hash = self.get_last_block().block_hash.clone();
+        self.mining_bits = self.get_next_bits(&tip_hash).unwrap();
+        self.mining_reward = self.get_next_reward(&tip_hash).unwrap();
+    }
+
+    /// Target (in compact form) a block mined on top of `parent_hash` must have.
+    ///
+    /// It is the parent target, except every `difficulty_adjustment_interval`
+    /// blocks where it gets retargeted from the time the last window of blocks took.
+    pub fn get_next_bits(&self, parent_hash: &str) -> Option<u32> {
+        self.get_next_bits_after(parent_hash, &[])
+    }
+
+    /// Same as `get_next_bits` for a block mined on top of `headers`, a chain
+    /// of headers not known yet following the known block `fork_hash`.
+    pub fn get_next_bits_after(
+        &self,
+        fork_hash: &str,
+        headers: &[MiningBlockHeader],
+    ) -> Option<u32> {
+        let fork = &self.get_known_block(fork_hash)?.block_header;
+        let known = std::iter::successors(Some(fork), |header| {
+            Some(&self.get_known_block(&header.prev_hash)?.block_header)
+        })
+        .map(|header| (header.block_number, header.created_at, header.bits));
+        // From the parent back to the genesis block
+        let mut ancestors = headers
+            .iter()
+            .rev()
+            .map(|header| (header.block_number, header.created_at, header.bits))
+            .chain(known);
+        let (parent_number, parent_created_at, parent_bits) = ancestors.next()?;
+        let bits = match parent_number {
+            0 => self.params.initial_bits,
+            _ => parent_bits,
+        };
+        if !self.params.is_adjustment_height(parent_number + 1) {
+            return Some(bits);
+        }
+        let window: Vec<i64> = ancestors
+            .take(self.params.difficulty_adjustment_interval as usize)
+            .map(|(_, created_at, _)| created_at)
+            .collect();
+        let first_created_at = window.last().copied().unwrap_or(parent_created_at);
+        Some(self.params.retarget(
+            bits,
+            window.len() as u64,
+            parent_created_at - first_created_at,
+        ))
+    }
+
+    /// Highest reward a block mined on top of `parent_hash` can claim, see
+    /// `ConsensusParams::get_block_reward`.
+    pub fn get_next_reward(&self, parent_hash: &str) -> Option<BigDecimal> {
+        let parent = self.get_known_block(parent_hash)?;
+        // Only needed to enforce the max supply, which takes walking the chain
+        let minted = match self.params.max_supply {
+            Some(_) => self.get_minted_supply(parent_hash),
+            None => BigDecimal::zero(),
+        };
+        Some(
+            self.params
+                .get_block_reward(parent.block_header.block_number + 1, &minted),
+        )
+    }
+
+    /// Switches the active chain to the branch ending at `new_tip`.
+    ///
+    /// If a block of the branch turns out to be invalid, it is dropped along
+    /// with its descendants and the previous active chain is restored.
+    fn reorganize(&mut self, new_tip: &str) -> Result<(), BlockError> {
+        let mut branch = Vec::new();
+        let mut fork_hash = new_tip.to_string();
+        while let Some(block) = self.side_blocks.get(&fork_hash) {
+            branch.push(fork_hash.clone());
+            fork_hash = block.block_header.prev_hash.clone();
+        }
+        branch.reverse();
+        let fork_number = self
+            .get_block_by_hash(&fork_hash)
+            .unwrap()
+            .block_header
+            .block_number;
+
+        let detached = self.detach_after(fork_number);
+        for (i, block_hash) in branch.iter().enumerate() {
+            let block = self.side_blocks.remove(block_hash).unwrap();
+            if let Err(err) = self.connect_block(block) {
+                for invalid_hash in &branch[i + 1..] {
+                    self.side_blocks.remove(invalid_hash);
+                }
+                self.detach_after(fork_number);
+                for block_hash in detached {
+                    let block = self.side_blocks.remove(&block_hash).unwrap();
+                    self.connect_block(block)
+                        .expect("Previously active block must still be valid");
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
     }
 
-    pub fn add_block(&mut self, header_mined: MiningBlockHeader, new_block: &Block) {
-        assert!(self.is_mined_block_valid(&header_mined));
-        let miner_address = header_mined.miner_address;
-        for tx in new_block.txs.values() {
-            self.state.apply_tx(tx, &miner_address);
+    /// Moves every active block above `block_number` to the side branches,
+    /// returning their hashes from the lowest to the highest.
+    fn detach_after(&mut self, block_number: u64) -> Vec<String> {
+        let mut detached = Vec::new();
+        while self.blocks.len() as u64 > block_number + 1 {
+            let block = self.disconnect_tip().unwrap();
+            detached.push(block.block_hash.clone());
+            self.side_blocks.insert(block.block_hash.clone(), block);
         }
-        self.state
-            .apply_mining_reward(&miner_address, &header_mined.reward);
-        self.blocks.push(new_block.clone());
+        detached.reverse();
+        detached
     }
 
-    fn is_mined_block_valid(&self, header: &MiningBlockHeader) -> bool {
-        assert!(header.is_pow_computation_valid());
-        let current_nb_blocks = self.blocks.len() as u64;
-        assert!(header.block_number == current_nb_blocks);
-        if current_nb_blocks > 0 {
-            let last_block_hash = &self.blocks.last().unwrap().block_hash;
-            assert!(last_block_hash == &header.prev_hash);
+    fn is_mined_block_valid(&self, header: &BlockHeader) -> Result<(), MiningError> {
+        if !header.is_pow_valid() {
+            return Err(MiningError::InvalidProofOfWork);
+        }
+        let parent = self
+            .get_known_block(&header.prev_hash)
+            .ok_or(MiningError::InvalidPrevHash)?;
+        let parent_number = parent.block_header.block_number;
+        if header.block_number != parent_number + 1 {
+            return Err(MiningError::InvalidBlockNumber {
+                expected: parent_number + 1,
+                got: header.block_number,
+            });
+        }
+        let expected_bits = self.get_next_bits(&header.prev_hash).unwrap();
+        if header.bits != expected_bits {
+            return Err(MiningError::InvalidDifficulty {
+                expected: expected_bits,
+                got: header.bits,
+            });
+        }
+        let max_reward = self.get_next_reward(&header.prev_hash).unwrap();
+        if header.reward > max_reward {
+            return Err(MiningError::InvalidReward {
+                expected: max_reward,
+                got: header.reward.clone(),
+            });
+        }
+        if header.created_at < parent.block_header.created_at
+            || header.created_at > Utc::now().timestamp() + MAX_FUTURE_BLOCK_TIME
+        {
+            return Err(MiningError::InvalidTimestamp);
+        }
+        Ok(())
+    }
+
+    pub fn is_known_block(&self, block_hash: &str) -> bool {
+        self.get_known_block(block_hash).is_some()
+    }
+
+    /// Looks for a block in the active chain then in the side branches.
+    pub fn get_known_block(&self, block_hash: &str) -> Option<&Block> {
+        self.get_block_by_hash(block_hash)
+            .or_else(|| self.side_blocks.get(block_hash))
+    }
+
+    /// Cumulative work of the active chain.
+    pub fn get_total_work(&self) -> BigUint {
+        self.get_work_until(self.blocks.len() as u64 - 1)
+    }
+
+    /// Work of the active chain up to `block_number`, the genesis block
+    /// being mined by nobody.
+    fn get_work_until(&self, block_number: u64) -> BigUint {
+        self.blocks
+            .iter()
+            .take(block_number as usize + 1)
+            .skip(1)
+            .map(|block| block.block_header.get_work())
+            .sum()
+    }
+
+    /// Cumulative work of the chain ending at a known block, active or not.
+    pub fn get_chain_work(&self, block_hash: &str) -> Option<BigUint> {
+        self.is_known_block(block_hash)
+            .then(|| self.get_branch_work(block_hash))
+    }
+
+    /// Cumulative work of the chain ending at the given side block.
+    fn get_branch_work(&self, block_hash: &str) -> BigUint {
+        let mut work = BigUint::ZERO;
+        let mut hash = block_hash;
+        while let Some(block) = self.side_blocks.get(hash) {
+            work += block.block_header.get_work();
+            hash = &block.block_header.prev_hash;
+        }
+        let fork_number = self
+            .get_block_by_hash(hash)
+            .unwrap()
+            .block_header
+            .block_number;
+        work + self.get_work_until(fork_number)
+    }
+
+    /// Money issued by the active chain: the genesis allocations and the
+    /// reward of every mined block, less the burned fees. It must match the
+    /// state total supply.
+    pub fn get_issued_supply(&self) -> BigDecimal {
+        let burned_fees: BigDecimal = self
+            .blocks
+            .iter()
+            .map(|block| self.params.get_burned_fees(&block.block_info.total_fees))
+            .sum();
+        self.get_minted_supply(&self.get_last_block().block_hash) - burned_fees
+    }
+
+    /// Money minted by the chain ending at a known block, active or not: the
+    /// genesis allocations and the reward of every mined block.
+    ///
+    /// The active chain total is kept in the journals, only the blocks of a
+    /// side branch are walked.
+    fn get_minted_supply(&self, block_hash: &str) -> BigDecimal {
+        let mut minted = BigDecimal::zero();
+        let mut hash = block_hash;
+        while let Some(block) = self.side_blocks.get(hash) {
+            minted += &block.block_header.reward;
+            hash = &block.block_header.prev_hash;
+        }
+        let fork_number = self
+            .get_block_by_hash(hash)
+            .unwrap()
+            .block_header
+            .block_number;
+        match fork_number {
+            0 => {
+                minted
+                    + self.blocks[0]
+                        .txs
+                        .values()
+                        .map(|tx| &tx.value)
+                        .sum::<BigDecimal>()
+            }
+            _ => minted + &self.journals[fork_number as usize - 1].minted,
         }
-        true
     }
 
     pub fn get_last_block(&self) -> &Block {
         self.blocks.last().unwrap()
     }
+
+    pub fn get_block(&self, block_number: u64) -> Option<&Block> {
+        self.blocks.get(block_number as usize)
+    }
+
+    pub fn get_block_by_hash(&self, block_hash: &str) -> Option<&Block> {
+        self.blocks
+            .iter()
+            .find(|block| block.block_hash == block_hash)
+    }
+
+    pub fn get_latest_blocks(&self, n: usize) -> Vec<&Block> {
+        self.blocks.iter().rev().take(n).collect()
+    }
+
+    pub fn find_tx(&self, tx_hash: &str) -> Option<(&Block, &Transaction)> {
+        self.blocks
+            .iter()
+            .find_map(|block| block.get_tx(tx_hash).map(|tx| (block, tx)))
+    }
 }
 
 impl Hashable for Blockchain {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mempool::Mempool;
+    use crate::miner::Miner;
+    use crate::pow::POW_LIMIT_BITS;
+    use crate::wallet::Wallet;
+
+    // Targets of 2^256 and 1, which any (or no) hash meets.
+    const ANY_HASH_BITS: u32 = 0x21010000;
+    const NO_HASH_BITS: u32 = 0x03000001;
+
+    fn get_test_blockchain() -> (Blockchain, Miner) {
+        let wallet = Wallet::new();
+        let txs = [Transaction::allocation(wallet.address(), 100_000.into(), 0)];
+        let blockchain = Blockchain::from_genesis_block(Block::genesis(&txs).unwrap()).unwrap();
+        (blockchain, Miner::new(wallet))
+    }
+
+    fn mine_next(blockchain: &Blockchain, miner: &mut Miner) -> Block {
+        let txs = vec![miner.build_coinbase(blockchain)];
+        let header = miner
+            .mine_next_block(blockchain, &txs, Some(u64::MAX))
+            .unwrap();
+        blockchain.build_block_candidate(&header, &txs).unwrap()
+    }
+
+    #[test]
+    fn test_add_mined_block() {
+        let (mut blockchain, mut miner) = get_test_blockchain();
+        let block = mine_next(&blockchain, &mut miner);
+        assert!(blockchain.add_block(&block).is_ok());
+        assert_eq!(blockchain.blocks.len(), 2);
+        assert_eq!(
+            blockchain.get_issued_supply(),
+            BigDecimal::from(100_000) + &blockchain.mining_reward
+        );
+        assert_eq!(
+            blockchain.state.get_total_supply(),
+            blockchain.get_issued_supply()
+        );
+    }
+
+    #[test]
+    fn test_block_is_verified_from_its_header() {
+        let (blockchain, mut miner) = get_test_blockchain();
+        let block = mine_next(&blockchain, &mut miner);
+        assert_eq!(block.block_header.miner_address, miner.get_address());
+        assert!(block.verify().is_ok());
+
+        // The block hash commits to the miner, the reward & the PoW fields
+        let mut tampered = block.clone();
+        tampered.block_header.bits = ANY_HASH_BITS;
+        tampered.block_header.miner_address = Address::named("thief");
+        assert_eq!(tampered.verify(), Err(BlockError::InvalidBlockHash));
+
+        tampered.block_header.bits = NO_HASH_BITS;
+        tampered.block_hash = tampered.block_header.get_hash();
+        assert_eq!(
+            tampered.verify(),
+            Err(BlockError::InvalidMinedHeader(
+                MiningError::InvalidProofOfWork
+            ))
+        );
+    }
+
+    #[test]
+    fn test_add_block_verifies_the_block() {
+        let (mut blockchain, mut miner) = get_test_blockchain();
+        let block = mine_next(&blockchain, &mut miner);
+
+        let mut tampered = block.clone();
+        tampered.block_info.volume += BigDecimal::from(1);
+        assert_eq!(
+            blockchain.add_block(&tampered),
+            Err(BlockError::InvalidBlockHeader)
+        );
+        let mut tampered = block.clone();
+        tampered.block_hash = String::from("0");
+        assert_eq!(
+            blockchain.add_block(&tampered),
+            Err(BlockError::InvalidBlockHash)
+        );
+        assert_eq!(blockchain.blocks.len(), 1);
+        assert!(blockchain.add_block(&block).is_ok());
+    }
+
+    #[test]
+    fn test_reorganize_to_heaviest_branch() {
+        let (mut blockchain, mut miner_a) = get_test_blockchain();
+        let mut miner_b = Miner::new(Wallet::new());
+        let genesis = blockchain.get_last_block().clone();
+
+        let block_a1 = mine_next(&blockchain, &mut miner_a);
+        let block_b1 = mine_next(&blockchain, &mut miner_b);
+        blockchain.add_block(&block_a1).unwrap();
+        let balance_a = blockchain.state.get_balance(&miner_a.get_address());
+
+        // Same amount of work: the first block seen stays on the active chain
+        blockchain.add_block(&block_b1).unwrap();
+        assert_eq!(blockchain.get_last_block().block_hash, block_a1.block_hash);
+        assert!(blockchain.side_blocks.contains_key(&block_b1.block_hash));
+        assert_eq!(
+            blockchain.add_block(&block_b1),
+            Err(BlockError::DuplicatedBlock)
+        );
+
+        let txs = vec![miner_b.build_coinbase(&blockchain)];
+        let header_b2 = miner_b
+            .mine(
+                &txs,
+                &block_b1.block_header,
+                &block_b1.block_hash,
+                blockchain.mining_bits,
+                &blockchain.mining_reward,
+                u64::MAX,
+            )
+            .unwrap();
+        let block_b2 = blockchain.build_block_candidate(&header_b2, &txs).unwrap();
+        blockchain.add_block(&block_b2).unwrap();
+
+        assert_eq!(blockchain.blocks.len(), 3);
+        assert_eq!(blockchain.blocks[0].block_hash, genesis.block_hash);
+        assert_eq!(blockchain.blocks[1].block_hash, block_b1.block_hash);
+        assert_eq!(blockchain.get_last_block().block_hash, block_b2.block_hash);
+        assert!(blockchain.side_blocks.contains_key(&block_a1.block_hash));
+        assert_eq!(
+            blockchain.state.get_balance(&miner_a.get_address()),
+            balance_a - &blockchain.mining_reward
+        );
+        assert_eq!(
+            blockchain.state.get_balance(&miner_b.get_address()),
+            BigDecimal::from(2)
+        );
+        assert_eq!(
+            blockchain.get_total_work(),
+            block_b1.block_header.get_work() * 2u32
+        );
+    }
+
+    #[test]
+    fn test_reorganization_gives_back_disconnected_txs() {
+        let (mut blockchain, mut miner_a) = get_test_blockchain();
+        let mut miner_b = Miner::new(Wallet::new());
+        let tx = miner_a
+            .wallet
+            .send(Address::named("adel.eth"), BigDecimal::from(5));
+        let txs = vec![miner_a.build_coinbase(&blockchain), tx.clone()];
+        let header_a1 = miner_a
+            .mine_next_block(&blockchain, &txs, Some(u64::MAX))
+            .unwrap();
+        let block_a1 = blockchain.build_block_candidate(&header_a1, &txs).unwrap();
+        let block_b1 = mine_next(&blockchain, &mut miner_b);
+        blockchain.add_block(&block_a1).unwrap();
+        blockchain.add_block(&block_b1).unwrap();
+        assert!(blockchain.take_disconnected_txs().is_empty());
+
+        let txs = vec![miner_b.build_coinbase(&blockchain)];
+        let header_b2 = miner_b
+            .mine(
+                &txs,
+                &block_b1.block_header,
+                &block_b1.block_hash,
+                blockchain.mining_bits,
+                &blockchain.mining_reward,
+                u64::MAX,
+            )
+            .unwrap();
+        let block_b2 = blockchain.build_block_candidate(&header_b2, &txs).unwrap();
+        blockchain.add_block(&block_b2).unwrap();
+        assert_eq!(blockchain.get_last_block().block_hash, block_b2.block_hash);
+
+        let disconnected = blockchain.take_disconnected_txs();
+        assert_eq!(disconnected.len(), 1);
+        assert_eq!(disconnected[0].get_hash(), tx.get_hash());
+        let mut mempool = Mempool::new();
+        mempool.reinject(disconnected, &blockchain.state);
+        assert!(mempool.get(&tx.get_hash()).is_some());
+    }
+
+    #[test]
+    fn test_reopen_from_block_store() {
+        let dir = std::env::temp_dir().join(format!("bfs-chain-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let (blockchain, mut miner) = get_test_blockchain();
+        let genesis = blockchain.get_last_block().clone();
+
+        let mut blockchain = Blockchain::create(&dir, genesis, ConsensusParams::default()).unwrap();
+        let block = mine_next(&blockchain, &mut miner);
+        blockchain.add_block(&block).unwrap();
+        let miner_balance = blockchain.state.get_balance(&miner.get_address());
+        drop(blockchain);
+
+        let blockchain = Blockchain::open(&dir, ConsensusParams::default()).unwrap();
+        assert_eq!(blockchain.blocks.len(), 2);
+        assert_eq!(blockchain.get_last_block().block_hash, block.block_hash);
+        assert_eq!(
+            blockchain.state.get_balance(&miner.get_address()),
+            miner_balance
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_block_failing_to_be_stored_is_forgotten() {
+        let dir = std::env::temp_dir().join(format!("bfs-chain-full-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let (blockchain, mut miner) = get_test_blockchain();
+        let genesis = blockchain.get_last_block().clone();
+
+        let mut blockchain = Blockchain::create(&dir, genesis, ConsensusParams::default()).unwrap();
+        let accounts_before = blockchain.state.state.clone();
+        let block = mine_next(&blockchain, &mut miner);
+        blockchain.store.as_mut().unwrap().make_read_only();
+        assert_eq!(
+            blockchain.add_block(&block),
+            Err(BlockError::StorageFailure)
+        );
+        assert_eq!(blockchain.blocks.len(), 1);
+        assert!(!blockchain.is_known_block(&block.block_hash));
+        assert_eq!(blockchain.state.state, accounts_before);
+        drop(blockchain);
+
+        let mut blockchain = Blockchain::open(&dir, ConsensusParams::default()).unwrap();
+        assert_eq!(blockchain.blocks.len(), 1);
+        blockchain.add_block(&block).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_json_file_replays_chain() {
+        let file_path = std::env::temp_dir().join(format!("bfs-load-{}.json", std::process::id()));
+        let file_path = file_path.to_str().unwrap();
+        let (mut blockchain, mut miner) = get_test_blockchain();
+        let block = mine_next(&blockchain, &mut miner);
+        blockchain.add_block(&block).unwrap();
+
+        let genesis_hash = blockchain.blocks[0].block_hash.clone();
+        let params = ConsensusParams::default();
+        let load = |file_path| Blockchain::load_json_file(file_path, &genesis_hash, &params);
+
+        blockchain.to_json_file(file_path);
+        let loaded = load(file_path).unwrap();
+        assert_eq!(loaded.blocks.len(), 2);
+        assert_eq!(loaded.state.state, blockchain.state.state);
+        assert!(matches!(
+            Blockchain::load_json_file(file_path, &block.block_hash, &params),
+            Err(LoadError::WrongGenesis { .. })
+        ));
+
+        // Rules are the verifier's ones, not the file's ones
+        blockchain.params.block_reward = BigDecimal::from(1_000);
+        blockchain.to_json_file(file_path);
+        assert!(matches!(load(file_path), Err(LoadError::ParamsMismatch)));
+        blockchain.params = params.clone();
+        let invalid_params = ConsensusParams {
+            fee_burn_fraction: BigDecimal::from(2),
+            ..params.clone()
+        };
+        assert!(matches!(
+            Blockchain::load_json_file(file_path, &genesis_hash, &invalid_params),
+            Err(LoadError::InvalidParams(
+                ParamsError::InvalidFeeBurnFraction
+            ))
+        ));
+
+        let tx_hash = block.txs.keys().next().unwrap().clone();
+        blockchain.blocks[1].txs[&tx_hash].value = BigDecimal::from(1_000);
+        blockchain.to_json_file(file_path);
+        assert!(matches!(
+            load(file_path),
+            Err(LoadError::InvalidBlock {
+                block_number: 1,
+                error: BlockError::InvalidTransaction
+            })
+        ));
+
+        blockchain.blocks[1] = block;
+        blockchain
+            .state
+            .get_mut(&miner.get_address())
+            .add_balance(&BigDecimal::from(1));
+        blockchain.to_json_file(file_path);
+        assert!(matches!(
+            load(file_path),
+            Err(LoadError::StateMismatch { address }) if address == miner.get_address()
+        ));
+        std::fs::remove_file(file_path).unwrap();
+    }
+
+    #[test]
+    fn test_reward_schedule_and_fee_burn_are_enforced() {
+        let (blockchain, mut miner) = get_test_blockchain();
+        let params = ConsensusParams {
+            initial_bits: POW_LIMIT_BITS,
+            difficulty_adjustment_interval: 0,
+            block_reward: BigDecimal::from(50),
+            halving_interval: 1,
+            max_supply: Some(BigDecimal::from(100_060)),
+            fee_burn_fraction: "0.5".parse().unwrap(),
+            ..ConsensusParams::default()
+        };
+        let genesis = blockchain.get_last_block().clone();
+        let mut blockchain = Blockchain::new(genesis, params).unwrap();
+        assert_eq!(blockchain.mining_reward, BigDecimal::from(50));
+
+        // Reward of 50 and half of the fee of 4
+        let coinbase = Transaction::coinbase(miner.get_address(), BigDecimal::from(52), 1);
+        let tx = miner.wallet.send_with_fee(
+            Address::named("adel.eth"),
+            BigDecimal::from(10),
+            BigDecimal::from(4),
+        );
+        let txs = vec![coinbase, tx];
+        let mut mine_with_reward = |blockchain: &Blockchain, reward: u64| {
+            let last_block = blockchain.get_last_block();
+            let header = miner
+                .mine(
+                    &txs,
+                    &last_block.block_header,
+                    &last_block.block_hash,
+                    blockchain.mining_bits,
+                    &BigDecimal::from(reward),
+                    u64::MAX,
+                )
+                .unwrap();
+            blockchain.build_block_candidate(&header, &txs).unwrap()
+        };
+
+        let block = mine_with_reward(&blockchain, 51);
+        assert_eq!(
+            blockchain.add_block(&block),
+            Err(BlockError::InvalidMinedHeader(MiningError::InvalidReward {
+                expected: BigDecimal::from(50),
+                got: BigDecimal::from(51)
+            }))
+        );
+        let block = mine_with_reward(&blockchain, 50);
+        blockchain.add_block(&block).unwrap();
+
+        // Half of the fee is burned, the reward is halved & capped by the max supply
+        assert_eq!(
+            blockchain.state.get_balance(&miner.get_address()),
+            BigDecimal::from(100_000 - 10 - 4 + 52)
+        );
+        assert_eq!(blockchain.get_issued_supply(), BigDecimal::from(100_048));
+        assert_eq!(blockchain.journals[0].minted, BigDecimal::from(100_050));
+        assert_eq!(
+            blockchain.state.get_total_supply(),
+            blockchain.get_issued_supply()
+        );
+        assert_eq!(blockchain.mining_reward, BigDecimal::from(10));
+    }
+
+    #[test]
+    fn test_difficulty_is_retargeted_and_enforced() {
+        let (blockchain, mut miner) = get_test_blockchain();
+        let params = ConsensusParams {
+            initial_bits: POW_LIMIT_BITS,
+            difficulty_adjustment_interval: 2,
+            target_block_time: 600,
+            ..ConsensusParams::default()
+        };
+        let genesis = blockchain.get_last_block().clone();
+        let mut blockchain = Blockchain::new(genesis, params).unwrap();
+
+        let block = mine_next(&blockchain, &mut miner);
+        assert_eq!(block.block_header.bits, POW_LIMIT_BITS);
+        blockchain.add_block(&block).unwrap();
+        // Blocks come way faster than targeted: the target is divided by 4
+        assert_eq!(blockchain.mining_bits, 0x201fffff);
+
+        let mut block = mine_next(&blockchain, &mut miner);
+        block.block_header.bits = ANY_HASH_BITS;
+        block.block_hash = block.block_header.get_hash();
+        assert_eq!(
+            blockchain.add_block(&block),
+            Err(BlockError::InvalidMinedHeader(
+                MiningError::InvalidDifficulty {
+                    expected: 0x201fffff,
+                    got: ANY_HASH_BITS
+                }
+            ))
+        );
+
+        blockchain.revert_last_block().unwrap();
+        assert_eq!(blockchain.mining_bits, POW_LIMIT_BITS);
+    }
+
+    #[test]
+    fn test_revert_last_block() {
+        let (mut blockchain, mut miner) = get_test_blockchain();
+        let miner_balance = blockchain.state.get_balance(&miner.get_address());
+        let block = mine_next(&blockchain, &mut miner);
+        blockchain.add_block(&block).unwrap();
+        assert_ne!(
+            blockchain.state.get_balance(&miner.get_address()),
+            miner_balance
+        );
+
+        let reverted = blockchain.revert_last_block().unwrap();
+        assert_eq!(reverted.block_hash, block.block_hash);
+        assert_eq!(
+            blockchain.state.get_balance(&miner.get_address()),
+            miner_balance
+        );
+        assert!(blockchain.revert_last_block().is_none());
+    }
+
+    #[test]
+    fn test_add_block_rejects_invalid_headers() {
+        let (mut blockchain, mut miner) = get_test_blockchain();
+        let block = mine_next(&blockchain, &mut miner);
+        let tampered = |tamper: fn(&mut BlockHeader)| {
+            let mut block = block.clone();
+            tamper(&mut block.block_header);
+            block.block_hash = block.block_header.get_hash();
+            block
+        };
+
+        // The coinbase nonce no longer matches the block number
+        let bad_number = tampered(|header| {
+            header.block_number = 2;
+            header.bits = ANY_HASH_BITS;
+        });
+        assert_eq!(
+            blockchain.add_block(&bad_number),
+            Err(BlockError::InvalidCoinbase)
+        );
+
+        let bad_prev_hash = tampered(|header| {
+            header.prev_hash = String::from("0");
+            header.bits = ANY_HASH_BITS;
+        });
+        assert_eq!(
+            blockchain.add_block(&bad_prev_hash),
+            Err(BlockError::InvalidMinedHeader(MiningError::InvalidPrevHash))
+        );
+
+        let bad_pow = tampered(|header| header.bits = NO_HASH_BITS);
+        assert_eq!(
+            blockchain.add_block(&bad_pow),
+            Err(BlockError::InvalidMinedHeader(
+                MiningError::InvalidProofOfWork
+            ))
+        );
+        assert_eq!(blockchain.blocks.len(), 1);
+    }
+}
@@ -0,0 +1,714 @@
+use core::fmt;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::thread;
+
+use bigdecimal::BigDecimal;
+use chrono::Utc;
+use clap::{Parser, Subcommand};
+
+use crate::address::Address;
+use crate::api::{ApiServer, BlockDetails, TxDetails, API_DEFAULT_ADDRESS};
+use crate::block::BlockError;
+use crate::block_store::{BlockStore, StoreError, BLOCK_STORE_FILE};
+use crate::block_template::BlockLimits;
+use crate::blockchain::{Blockchain, LoadError};
+use crate::genesis::{GenesisConfig, GenesisError};
+use crate::hashable::Hashable;
+use crate::hd_wallet::{HdWallet, HdWalletError};
+use crate::keystore::KeystoreError;
+use crate::mempool::{Mempool, MempoolError};
+use crate::miner::Miner;
+use crate::mining_session::MINING_PROGRESS_INTERVAL;
+use crate::p2p::{Node, P2P_DEFAULT_ADDRESS};
+use crate::transaction::Transaction;
+use crate::wallet::Wallet;
+
+pub const DEFAULT_DATA_DIR: &str = "my_blockchain";
+pub const WALLET_FILE: &str = "wallet.json";
+/// Signed transactions waiting to be mined, kept between two commands.
+pub const MEMPOOL_FILE: &str = "mempool.json";
+/// Specification the chain was created from, see `GenesisConfig`.
+pub const GENESIS_FILE: &str = "genesis.json";
+/// Allocated to the wallet by the genesis block when no genesis file is given.
+pub const DEFAULT_ALLOCATION: u64 = 100_000;
+
+#[derive(Parser)]
+#[command(name = "bfs", about = "🚀 [BFS: Blockchain From Scratch]")]
+pub struct Cli {
+    /// Directory holding the block store, the wallet and the pending transactions.
+    #[arg(long, global = true, default_value = DEFAULT_DATA_DIR)]
+    pub dir: PathBuf,
+    /// Wallet file, `<dir>/wallet.json` by default.
+    #[arg(long, global = true)]
+    pub wallet: Option<PathBuf>,
+    /// Password of the wallet, which is then saved as an encrypted keystore.
+    #[arg(
+        long,
+        global = true,
+        env = "BFS_WALLET_PASSWORD",
+        hide_env_values = true
+    )]
+    pub password: Option<String>,
+    /// Allows saving a new wallet without a password, its key in clear.
+    #[arg(long, global = true)]
+    pub unencrypted: bool,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Creates the wallet (unless it exists) and a new chain from a genesis block.
+    Init {
+        /// Genesis specification (JSON or TOML), funding the wallet by default.
+        #[arg(long)]
+        genesis: Option<PathBuf>,
+        /// Derives the wallet from a mnemonic phrase instead of a random key.
+        #[arg(long, env = "BFS_MNEMONIC", hide_env_values = true)]
+        mnemonic: Option<String>,
+        /// Index of the account derived from the mnemonic.
+        #[arg(long, default_value_t = 0)]
+        account: u32,
+        /// BIP-39 passphrase of the mnemonic, none by default.
+        #[arg(
+            long,
+            env = "BFS_MNEMONIC_PASSPHRASE",
+            hide_env_values = true,
+            default_value = ""
+        )]
+        passphrase: String,
+    },
+    /// Lists the first accounts of a mnemonic phrase, a new one by default.
+    Accounts {
+        #[arg(long, env = "BFS_MNEMONIC", hide_env_values = true)]
+        mnemonic: Option<String>,
+        #[arg(long, default_value_t = 10)]
+        count: u32,
+        /// BIP-39 passphrase of the mnemonic, none by default.
+        #[arg(
+            long,
+            env = "BFS_MNEMONIC_PASSPHRASE",
+            hide_env_values = true,
+            default_value = ""
+        )]
+        passphrase: String,
+    },
+    /// Mines blocks with the pending transactions, rewarding the wallet.
+    Mine {
+        #[arg(long, default_value_t = 1)]
+        blocks: u64,
+        /// Worker threads, all the available cores by default.
+        #[arg(long)]
+        threads: Option<usize>,
+    },
+    /// Signs a transaction from the wallet and adds it to the pending ones.
+    Send {
+        /// Recipient, in its checksummed form.
+        #[arg(value_parser = Address::parse_checksummed)]
+        to: Address,
+        value: BigDecimal,
+        #[arg(long, default_value_t = BigDecimal::from(0))]
+        fee: BigDecimal,
+    },
+    /// Shows the balance & nonce of an account, the wallet one by default.
+    Balance { address: Option<Address> },
+    /// Shows a block of the active chain by number or hash.
+    ShowBlock { id: String },
+    /// Shows a mined transaction.
+    ShowTx { hash: String },
+    /// Writes the blockchain to a JSON file.
+    Export { file: PathBuf },
+    /// Replays a blockchain JSON file, checking every block.
+    Verify { file: PathBuf },
+    /// Runs a node sharing the chain with its peers & serving the explorer API.
+    Node {
+        #[arg(long, default_value = P2P_DEFAULT_ADDRESS)]
+        p2p: String,
+        #[arg(long, default_value = API_DEFAULT_ADDRESS)]
+        api: String,
+        /// Peer to connect to, can be repeated.
+        #[arg(long = "peer")]
+        peers: Vec<String>,
+        /// Keeps mining blocks with the transactions received.
+        #[arg(long)]
+        mine: bool,
+    },
+}
+
+#[derive(Debug)]
+pub enum CliError {
+    Io(io::Error),
+    NotInitialized,
+    AlreadyInitialized,
+    MissingGenesis,
+    /// The genesis block built from `genesis.json` isn't the one of the chain.
+    GenesisMismatch {
+        expected: String,
+        got: String,
+    },
+    /// A new wallet would be saved without a password nor `--unencrypted`.
+    PlaintextWallet,
+    /// A wallet can't be derived from a mnemonic over an existing one.
+    WalletExists,
+    NotFound,
+    Genesis(GenesisError),
+    HdWallet(HdWalletError),
+    Keystore(KeystoreError),
+    Store(StoreError),
+    Load(LoadError),
+    InvalidBlock(BlockError),
+    RejectedTransaction(MempoolError),
+}
+
+impl From<io::Error> for CliError {
+    fn from(err: io::Error) -> CliError {
+        CliError::Io(err)
+    }
+}
+
+impl From<GenesisError> for CliError {
+    fn from(err: GenesisError) -> CliError {
+        CliError::Genesis(err)
+    }
+}
+
+impl From<HdWalletError> for CliError {
+    fn from(err: HdWalletError) -> CliError {
+        CliError::HdWallet(err)
+    }
+}
+
+impl From<KeystoreError> for CliError {
+    fn from(err: KeystoreError) -> CliError {
+        CliError::Keystore(err)
+    }
+}
+
+impl From<StoreError> for CliError {
+    fn from(err: StoreError) -> CliError {
+        CliError::Store(err)
+    }
+}
+
+impl From<BlockError> for CliError {
+    fn from(err: BlockError) -> CliError {
+        CliError::InvalidBlock(err)
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::NotInitialized => write!(f, "no chain found, run `bfs init` first"),
+            CliError::AlreadyInitialized => write!(f, "a chain already exists"),
+            CliError::MissingGenesis => {
+                write!(f, "the chain has no {} defining its rules", GENESIS_FILE)
+            }
+            CliError::GenesisMismatch { expected, got } => write!(
+                f,
+                "{} defines genesis block {} but the chain starts with {}",
+                GENESIS_FILE, expected, got
+            ),
+            CliError::PlaintextWallet => write!(
+                f,
+                "the wallet key would be saved in clear, set a password \
+                 (--password or BFS_WALLET_PASSWORD) or pass --unencrypted"
+            ),
+            CliError::WalletExists => write!(
+                f,
+                "a wallet already exists, remove it or pick another --wallet \
+                 to derive one from the mnemonic"
+            ),
+            CliError::NotFound => write!(f, "not found"),
+            CliError::Genesis(err) => write!(f, "{}", err),
+            CliError::HdWallet(err) => write!(f, "{}", err),
+            CliError::Keystore(err) => write!(f, "{}", err),
+            err => write!(f, "{:?}", err),
+        }
+    }
+}
+
+impl Cli {
+    pub fn run(self) -> Result<(), CliError> {
+        match &self.command {
+            Command::Init {
+                genesis,
+                mnemonic,
+                account,
+                passphrase,
+            } => {
+                let hd_account = match mnemonic {
+                    Some(phrase) => Some((HdWallet::from_phrase(phrase, passphrase)?, *account)),
+                    None => None,
+                };
+                self.init(genesis.as_deref(), hd_account)
+            }
+            Command::Accounts {
+                mnemonic,
+                count,
+                passphrase,
+            } => {
+                let phrase = match mnemonic {
+                    Some(phrase) => phrase.clone(),
+                    None => {
+                        let phrase = HdWallet::generate().phrase();
+                        println!("🔑 New mnemonic: {}", phrase);
+                        phrase
+                    }
+                };
+                let hd_wallet = HdWallet::from_phrase(&phrase, passphrase)?;
+                for (index, wallet) in hd_wallet.accounts(*count)?.iter().enumerate() {
+                    println!("#{} {}", index, wallet.address());
+                }
+                Ok(())
+            }
+            Command::Mine { blocks, threads } => self.mine(*blocks, *threads),
+            Command::Send { to, value, fee } => self.send(*to, value, fee),
+            Command::Balance { address } => {
+                let blockchain = self.open_blockchain()?;
+                let address = match address {
+                    Some(address) => *address,
+                    None => self.load_wallet()?.address(),
+                };
+                let account = blockchain.state.get_account(&address);
+                println!("{}\n{}", address, account);
+                Ok(())
+            }
+            Command::ShowBlock { id } => {
+                let blockchain = self.open_blockchain()?;
+                let block = match id.parse::<u64>() {
+                    Ok(block_number) => blockchain.get_block(block_number),
+                    Err(_) => blockchain.get_block_by_hash(id),
+                };
+                let block = block.ok_or(CliError::NotFound)?;
+                print_json(&BlockDetails::from(block));
+                Ok(())
+            }
+            Command::ShowTx { hash } => {
+                let blockchain = self.open_blockchain()?;
+                let (block, tx) = blockchain.find_tx(hash).ok_or(CliError::NotFound)?;
+                print_json(&TxDetails::new(block, hash, tx));
+                Ok(())
+            }
+            Command::Export { file } => {
+                let blockchain = self.open_blockchain()?;
+                blockchain.to_json_file(&file.to_string_lossy());
+                println!("💾 Blockchain written to {}", file.display());
+                Ok(())
+            }
+            Command::Verify { file } => {
+                // Checked against the rules of the local chain, not the file ones
+                let local = self.open_blockchain()?;
+                let blockchain = Blockchain::load_json_file(
+                    &file.to_string_lossy(),
+                    &local.blocks[0].block_hash,
+                    &local.params,
+                )
+                .map_err(CliError::Load)?;
+                println!(
+                    "✅ {} blocks verified, tip {}",
+                    blockchain.blocks.len(),
+                    blockchain.get_last_block().block_hash
+                );
+                Ok(())
+            }
+            Command::Node {
+                p2p,
+                api,
+                peers,
+                mine,
+            } => self.run_node(p2p, api, peers, *mine),
+        }
+    }
+
+    fn wallet_path(&self) -> PathBuf {
+        self.wallet
+            .clone()
+            .unwrap_or_else(|| self.dir.join(WALLET_FILE))
+    }
+
+    fn load_wallet(&self) -> Result<Wallet, CliError> {
+        match &self.password {
+            Some(password) => Ok(Wallet::load_encrypted(self.wallet_path(), password)?),
+            None => Ok(Wallet::load(self.wallet_path())?),
+        }
+    }
+
+    /// Saves the wallet as a keystore if there is a password. A new wallet
+    /// is only saved in clear with `--unencrypted`.
+    fn save_wallet(&self, wallet: &Wallet) -> Result<(), CliError> {
+        let wallet_path = self.wallet_path();
+        match &self.password {
+            Some(password) => wallet.save_encrypted(wallet_path, password)?,
+            None if !self.unencrypted && !wallet_path.exists() => {
+                return Err(CliError::PlaintextWallet)
+            }
+            None => wallet.save(wallet_path)?,
+        }
+        Ok(())
+    }
+
+    fn open_blockchain(&self) -> Result<Blockchain, CliError> {
+        if !BlockStore::exists(&self.dir) {
+            return Err(CliError::NotInitialized);
+        }
+        let genesis_path = self.dir.join(GENESIS_FILE);
+        if !genesis_path.exists() {
+            return Err(CliError::MissingGenesis);
+        }
+        let config = GenesisConfig::load(genesis_path)?;
+        let genesis_hash = config.genesis_block()?.block_hash;
+        let blockchain = Blockchain::open(&self.dir, config.params())?;
+        // The config may have been edited since the chain was created
+        let got = &blockchain.get_block(0).unwrap().block_hash;
+        if *got != genesis_hash {
+            return Err(CliError::GenesisMismatch {
+                expected: genesis_hash,
+                got: got.clone(),
+            });
+        }
+        Ok(blockchain)
+    }
+
+    /// Creates the chain, along with the wallet unless it exists. The wallet
+    /// is derived from `hd_account` (a mnemonic and an account index) if given.
+    fn init(
+        &self,
+        genesis_path: Option<&Path>,
+        hd_account: Option<(HdWallet, u32)>,
+    ) -> Result<(), CliError> {
+        if BlockStore::exists(&self.dir) {
+            return Err(CliError::AlreadyInitialized);
+        }
+        let wallet_path = self.wallet_path();
+        let wallet = match hd_account {
+            // The existing wallet would be kept, ignoring the mnemonic
+            Some(_) if wallet_path.exists() => return Err(CliError::WalletExists),
+            Some((hd_wallet, account)) => hd_wallet.account(account)?,
+            None if wallet_path.exists() => self.load_wallet()?,
+            None => Wallet::new(),
+        };
+        fs::create_dir_all(&self.dir)?;
+        self.save_wallet(&wallet)?;
+        let address = wallet.address();
+        let config = match genesis_path {
+            Some(path) => GenesisConfig::load(path)?,
+            None => GenesisConfig {
+                timestamp: Utc::now().timestamp(),
+                allocations: BTreeMap::from([(address, DEFAULT_ALLOCATION.into())]),
+                ..GenesisConfig::default()
+            },
+        };
+        let blockchain = Blockchain::create(&self.dir, config.genesis_block()?, config.params())?;
+        // A store left without its rules couldn't be opened
+        if let Err(err) = config.save(self.dir.join(GENESIS_FILE)) {
+            fs::remove_file(self.dir.join(BLOCK_STORE_FILE))?;
+            return Err(err.into());
+        }
+        println!("🎉 New chain created in {}", self.dir.display());
+        println!(
+            "🧱 Genesis block {}",
+            blockchain.get_last_block().block_hash
+        );
+        println!("👛 Wallet {} saved to {}", address, wallet_path.display());
+        Ok(())
+    }
+
+    fn send(&self, to: Address, value: &BigDecimal, fee: &BigDecimal) -> Result<(), CliError> {
+        let blockchain = self.open_blockchain()?;
+        let mut wallet = self.load_wallet()?;
+        wallet.chain_id = blockchain.params.chain_id;
+        let mut mempool = self.load_mempool(&blockchain)?;
+        // Next nonce after the mined and pending transactions of the wallet
+        let address = wallet.address();
+        wallet.nonce = mempool
+            .get_sender_txs(&address)
+            .map(|tx| tx.nonce + 1)
+            .max()
+            .unwrap_or(0)
+            .max(blockchain.state.get_account(&address).nonce);
+        let tx = wallet.send_with_fee(to, value.clone(), fee.clone());
+        let tx_hash = mempool
+            .add(tx, &blockchain.state)
+            .map_err(CliError::RejectedTransaction)?;
+        self.save_mempool(&mempool)?;
+        self.save_wallet(&wallet)?;
+        println!("📨 Transaction {} pending", tx_hash);
+        Ok(())
+    }
+
+    fn mine(&self, nb_blocks: u64, threads: Option<usize>) -> Result<(), CliError> {
+        let mut blockchain = self.open_blockchain()?;
+        let mut mempool = self.load_mempool(&blockchain)?;
+        let mut miner = Miner::new(self.load_wallet()?);
+        miner.nb_threads =
+            threads.unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+        for _ in 0..nb_blocks {
+            println!("⛏ Miner mining next block...");
+            let template = miner.build_template(&blockchain, &mempool, &BlockLimits::default());
+            let mut session = miner.start_template_session(&template);
+            let header_mined = session
+                .mine(u64::MAX, |progress| println!("{}", progress))
+                .map_err(|err| CliError::InvalidBlock(err.into()))?;
+            let new_block = blockchain.build_block_candidate(&header_mined, &template.txs)?;
+            blockchain.add_block(&new_block)?;
+            mempool.remove_block_txs(&new_block, &blockchain.state);
+            println!(
+                "🎉 Successfuly mined new block #{}! {}\n",
+                new_block.block_header.block_number,
+                session.get_progress()
+            );
+        }
+        self.save_mempool(&mempool)
+    }
+
+    fn run_node(
+        &self,
+        p2p_address: &str,
+        api_address: &str,
+        peers: &[String],
+        mine: bool,
+    ) -> Result<(), CliError> {
+        let blockchain = self.open_blockchain()?;
+        let mempool = self.load_mempool(&blockchain)?;
+        let blockchain = Arc::new(RwLock::new(blockchain));
+        let mempool = Arc::new(RwLock::new(mempool));
+
+        let node = Node::bind(p2p_address, blockchain.clone(), mempool.clone())?;
+        node.spawn();
+        println!("📡 P2P node listening on {}", node.local_addr());
+        for peer in peers {
+            match node.connect(peer) {
+                Ok(()) => println!("🤝 Connected to {}", peer),
+                Err(err) => println!("❌ Unable to connect to {}: {}", peer, err),
+            }
+        }
+        let mut server = ApiServer::bind(api_address, blockchain.clone(), mempool.clone())?;
+        server.node = Some(node.clone());
+        println!("🌐 API listening on http://{}", server.local_addr());
+        if !mine {
+            server.serve();
+            return Ok(());
+        }
+        server.spawn();
+
+        let mut miner = Miner::new(self.load_wallet()?);
+        miner.nb_threads = thread::available_parallelism().map_or(1, |n| n.get());
+        loop {
+            let template = miner.build_template(
+                &blockchain.read().unwrap(),
+                &mempool.read().unwrap(),
+                &BlockLimits::default(),
+            );
+            let mut session = miner.start_template_session(&template);
+            // Mines by small batches to start again on top of blocks received
+            let header_mined = loop {
+                if let Ok(header_mined) = session.mine(MINING_PROGRESS_INTERVAL, |_| {}) {
+                    break Some(header_mined);
+                }
+                let tip_hash = blockchain
+                    .read()
+                    .unwrap()
+                    .get_last_block()
+                    .block_hash
+                    .clone();
+                if tip_hash != template.header.prev_hash {
+                    break None;
+                }
+            };
+            let Some(header_mined) = header_mined else {
+                continue;
+            };
+            let mut chain = blockchain.write().unwrap();
+            // The template may have been invalidated meanwhile, by a reorg
+            let new_block = match chain.build_block_candidate(&header_mined, &template.txs) {
+                Ok(new_block) => new_block,
+                Err(err) => {
+                    println!("❌ Block candidate rejected: {:?}", err);
+                    continue;
+                }
+            };
+            if let Err(err) = chain.add_block(&new_block) {
+                println!("❌ Block rejected: {:?}", err);
+                continue;
+            }
+            mempool
+                .write()
+                .unwrap()
+                .remove_block_txs(&new_block, &chain.state);
+            drop(chain);
+            node.announce_block(&new_block);
+            println!(
+                "🎉 Successfuly mined new block #{}! {}",
+                new_block.block_header.block_number,
+                session.get_progress()
+            );
+        }
+    }
+
+    /// Loads the pending transactions still valid against the chain state.
+    fn load_mempool(&self, blockchain: &Blockchain) -> Result<Mempool, CliError> {
+        let mut mempool = Mempool::new();
+        let path = self.dir.join(MEMPOOL_FILE);
+        if !path.exists() {
+            return Ok(mempool);
+        }
+        let txs: Vec<Transaction> = serde_json::from_str(&fs::read_to_string(path)?)
+            .map_err(|err| CliError::Io(err.into()))?;
+        for tx in txs {
+            let _ = mempool.add(tx, &blockchain.state);
+        }
+        Ok(mempool)
+    }
+
+    fn save_mempool(&self, mempool: &Mempool) -> Result<(), CliError> {
+        let txs: Vec<&Transaction> = mempool.iter().map(|(_, tx)| tx).collect();
+        let json = serde_json::to_string_pretty(&txs).map_err(|err| CliError::Io(err.into()))?;
+        fs::write(self.dir.join(MEMPOOL_FILE), json)?;
+        Ok(())
+    }
+}
+
+fn print_json<T: serde::Serialize>(value: &T) {
+    println!("{}", serde_json::to_string_pretty(value).unwrap());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::consensus::ConsensusParams;
+
+    fn run(dir: &Path, args: &[&str]) -> Result<(), CliError> {
+        let mut argv = vec!["bfs", "--dir", dir.to_str().unwrap()];
+        argv.extend_from_slice(args);
+        Cli::parse_from(argv).run()
+    }
+
+    #[test]
+    fn test_init_send_mine_balance() {
+        let dir = env::temp_dir().join(format!("bfs-cli-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        assert!(matches!(
+            run(&dir, &["balance"]),
+            Err(CliError::NotInitialized)
+        ));
+        assert!(matches!(
+            run(&dir, &["init"]),
+            Err(CliError::PlaintextWallet)
+        ));
+        assert!(!dir.join(GENESIS_FILE).exists());
+        run(&dir, &["init", "--unencrypted"]).unwrap();
+        assert!(matches!(
+            run(&dir, &["init"]),
+            Err(CliError::AlreadyInitialized)
+        ));
+
+        let to = Address::named("adel.eth").to_string();
+        run(&dir, &["send", &to, "10", "--fee", "1"]).unwrap();
+        run(&dir, &["send", &to, "5"]).unwrap();
+        // A single-case recipient may hide a typo
+        assert!(Cli::try_parse_from(["bfs", "send", &to.to_lowercase(), "5"]).is_err());
+        run(&dir, &["mine", "--blocks", "2", "--threads", "2"]).unwrap();
+
+        let blockchain = Blockchain::open(&dir, ConsensusParams::default()).unwrap();
+        assert_eq!(blockchain.blocks.len(), 3);
+        assert_eq!(
+            blockchain.state.get_balance(&Address::named("adel.eth")),
+            BigDecimal::from(15)
+        );
+        let wallet = Wallet::load(dir.join(WALLET_FILE)).unwrap();
+        assert_eq!(blockchain.state.get_nonce(&wallet.address()), 2);
+        assert!(run(&dir, &["show-block", "1"]).is_ok());
+        assert!(matches!(
+            run(&dir, &["show-tx", "unknown"]),
+            Err(CliError::NotFound)
+        ));
+
+        let file = dir.join("chain.json");
+        run(&dir, &["export", file.to_str().unwrap()]).unwrap();
+        run(&dir, &["verify", file.to_str().unwrap()]).unwrap();
+
+        let mut config = GenesisConfig::load(dir.join(GENESIS_FILE)).unwrap();
+        config
+            .allocations
+            .insert(Address::named("thief.eth"), BigDecimal::from(1_000));
+        config.save(dir.join(GENESIS_FILE)).unwrap();
+        assert!(matches!(
+            run(&dir, &["balance"]),
+            Err(CliError::GenesisMismatch { .. })
+        ));
+        fs::remove_file(dir.join(GENESIS_FILE)).unwrap();
+        assert!(matches!(
+            run(&dir, &["balance"]),
+            Err(CliError::MissingGenesis)
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_init_from_mnemonic() {
+        let dir = env::temp_dir().join(format!("bfs-cli-mnemonic-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let phrase = "test test test test test test test test test test test junk";
+        run(
+            &dir,
+            &[
+                "init",
+                "--mnemonic",
+                phrase,
+                "--account",
+                "1",
+                "--unencrypted",
+            ],
+        )
+        .unwrap();
+
+        let wallet = Wallet::load(dir.join(WALLET_FILE)).unwrap();
+        let account = HdWallet::from_phrase(phrase, "")
+            .unwrap()
+            .account(1)
+            .unwrap();
+        assert_eq!(wallet.address(), account.address());
+        fs::remove_dir_all(&dir).unwrap();
+
+        // The mnemonic isn't silently ignored for the existing wallet
+        fs::create_dir_all(&dir).unwrap();
+        Wallet::new().save(dir.join(WALLET_FILE)).unwrap();
+        assert!(matches!(
+            run(&dir, &["init", "--mnemonic", phrase, "--unencrypted"]),
+            Err(CliError::WalletExists)
+        ));
+        fs::remove_file(dir.join(WALLET_FILE)).unwrap();
+
+        run(
+            &dir,
+            &[
+                "init",
+                "--mnemonic",
+                phrase,
+                "--passphrase",
+                "secret",
+                "--unencrypted",
+            ],
+        )
+        .unwrap();
+        let wallet = Wallet::load(dir.join(WALLET_FILE)).unwrap();
+        let account = HdWallet::from_phrase(phrase, "secret")
+            .unwrap()
+            .account(0)
+            .unwrap();
+        assert_eq!(wallet.address(), account.address());
+        let unprotected = HdWallet::from_phrase(phrase, "")
+            .unwrap()
+            .account(0)
+            .unwrap();
+        assert_ne!(wallet.address(), unprotected.address());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+use core::fmt;
+
+use bigdecimal::num_bigint::BigInt;
+use bigdecimal::{BigDecimal, Zero};
+use serde::{Deserialize, Serialize};
+
+use crate::pow::{compact_to_target, target_to_compact, BASE_MINING_BITS, POW_LIMIT_BITS};
+
+pub const DEFAULT_CHAIN_ID: u64 = 1;
+pub const DIFFICULTY_ADJUSTMENT_INTERVAL: u64 = 10;
+pub const TARGET_BLOCK_TIME: i64 = 10;
+/// Number of blocks between two halvings of the block reward.
+pub const HALVING_INTERVAL: u64 = 210_000;
+/// Halvings after which the block reward is considered spent.
+pub const MAX_HALVINGS: u64 = 64;
+/// How far in the future (in seconds) a block timestamp is allowed to be.
+pub const MAX_FUTURE_BLOCK_TIME: i64 = 2 * 60 * 60;
+
+#[derive(Debug, PartialEq)]
+pub enum ParamsError {
+    /// `fee_burn_fraction` is not between 0 and 1.
+    InvalidFeeBurnFraction,
+}
+
+impl fmt::Display for ParamsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamsError::InvalidFeeBurnFraction => {
+                write!(f, "fee_burn_fraction must be between 0 and 1")
+            }
+        }
+    }
+}
+
+/// Rules every node of a network must agree on.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConsensusParams {
+    /// Identifies the network, so that nodes of different ones don't mix.
+    pub chain_id: u64,
+    /// Target of the first block after the genesis one, in compact form.
+    pub initial_bits: u32,
+    /// Easiest target a block can have, in compact form.
+    pub pow_limit_bits: u32,
+    /// Number of blocks between two difficulty adjustments.
+    pub difficulty_adjustment_interval: u64,
+    /// Average time between two blocks the difficulty converges to, in seconds.
+    pub target_block_time: i64,
+    /// Reward of the first mined block.
+    pub block_reward: BigDecimal,
+    /// Number of blocks after which the reward is halved, 0 to never halve it.
+    pub halving_interval: u64,
+    /// Money that can ever be issued, genesis allocations included.
+    pub max_supply: Option<BigDecimal>,
+    /// Part of the transaction fees burned instead of paid to the miner,
+    /// between 0 and 1.
+    pub fee_burn_fraction: BigDecimal,
+}
+
+impl Default for ConsensusParams {
+    fn default() -> Self {
+        ConsensusParams {
+            chain_id: DEFAULT_CHAIN_ID,
+            initial_bits: BASE_MINING_BITS,
+            pow_limit_bits: POW_LIMIT_BITS,
+            difficulty_adjustment_interval: DIFFICULTY_ADJUSTMENT_INTERVAL,
+            target_block_time: TARGET_BLOCK_TIME,
+            block_reward: BigDecimal::from(1),
+            halving_interval: HALVING_INTERVAL,
+            max_supply: None,
+            fee_burn_fraction: BigDecimal::zero(),
+        }
+    }
+}
+
+impl ConsensusParams {
+    /// Checks the rules read from a file make sense.
+    pub fn validate(&self) -> Result<(), ParamsError> {
+        if self.fee_burn_fraction < BigDecimal::zero() || self.fee_burn_fraction > 1 {
+            return Err(ParamsError::InvalidFeeBurnFraction);
+        }
+        Ok(())
+    }
+
+    pub fn is_adjustment_height(&self, block_number: u64) -> bool {
+        self.difficulty_adjustment_interval > 0
+            && block_number.is_multiple_of(self.difficulty_adjustment_interval)
+    }
+
+    /// Reward of the block `block_number`, once `issued` has been issued by
+    /// the blocks before it.
+    ///
+    /// The reward is halved every `halving_interval` blocks, and never lets
+    /// the supply go over `max_supply`.
+    pub fn get_block_reward(&self, block_number: u64, issued: &BigDecimal) -> BigDecimal {
+        let halvings = match self.halving_interval {
+            0 => 0,
+            interval => (block_number.max(1) - 1) / interval,
+        };
+        if halvings >= MAX_HALVINGS {
+            return BigDecimal::zero();
+        }
+        // Exact halvings: x / 2^n = x * 5^n / 10^n
+        let halving = BigDecimal::new(BigInt::from(5).pow(halvings as u32), halvings as i64);
+        let reward = &self.block_reward * halving;
+        match &self.max_supply {
+            Some(max_supply) => reward.min((max_supply - issued).max(BigDecimal::zero())),
+            None => reward,
+        }
+    }
+
+    /// Part of `fees` burned instead of paid to the miner.
+    pub fn get_burned_fees(&self, fees: &BigDecimal) -> BigDecimal {
+        fees * &self.fee_burn_fraction
+    }
+
+    /// Target (in compact form) of the block following a window of
+    /// `nb_intervals` blocks mined in `timespan` seconds with target `bits`.
+    ///
+    /// The target is scaled by how much longer (or shorter) the window took than
+    /// expected, by a factor 4 at most, and never gets easier than the PoW limit.
+    pub fn retarget(&self, bits: u32, nb_intervals: u64, timespan: i64) -> u32 {
+        let expected_timespan = nb_intervals as i64 * self.target_block_time;
+        if expected_timespan <= 0 {
+            return bits;
+        }
+        let timespan = timespan
+            .clamp(expected_timespan / 4, expected_timespan * 4)
+            .max(1);
+        let target = compact_to_target(bits) * timespan as u64 / expected_timespan as u64;
+        target_to_compact(&target.min(compact_to_target(self.pow_limit_bits)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retarget() {
+        let params = ConsensusParams::default();
+        assert_eq!(params.retarget(0x1d00ffff, 10, 100), 0x1d00ffff);
+        assert_eq!(params.retarget(0x1d00ffff, 10, 50), 0x1c7fff80);
+        assert_eq!(params.retarget(0x1d00ffff, 10, 0), 0x1c3fffc0);
+        assert_eq!(params.retarget(0x1d00ffff, 10, 400), 0x1d03fffc);
+        assert_eq!(params.retarget(0x1d00ffff, 10, 1_000_000), 0x1d03fffc);
+        assert_eq!(params.retarget(POW_LIMIT_BITS, 10, 1_000), POW_LIMIT_BITS);
+    }
+
+    #[test]
+    fn test_block_reward_schedule() {
+        let params = ConsensusParams {
+            block_reward: BigDecimal::from(50),
+            halving_interval: 10,
+            max_supply: Some(BigDecimal::from(1_000)),
+            ..ConsensusParams::default()
+        };
+        let issued = BigDecimal::from(100);
+        assert_eq!(params.get_block_reward(1, &issued), BigDecimal::from(50));
+        assert_eq!(params.get_block_reward(10, &issued), BigDecimal::from(50));
+        assert_eq!(params.get_block_reward(11, &issued), BigDecimal::from(25));
+        assert_eq!(
+            params.get_block_reward(31, &issued),
+            "6.25".parse::<BigDecimal>().unwrap()
+        );
+        assert_eq!(params.get_block_reward(641, &issued), BigDecimal::zero());
+        assert_eq!(
+            params.get_block_reward(1, &BigDecimal::from(990)),
+            BigDecimal::from(10)
+        );
+        assert_eq!(
+            params.get_block_reward(1, &BigDecimal::from(1_000)),
+            BigDecimal::zero()
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut params = ConsensusParams::default();
+        assert_eq!(params.validate(), Ok(()));
+        params.fee_burn_fraction = BigDecimal::from(1);
+        assert_eq!(params.validate(), Ok(()));
+        params.fee_burn_fraction = "1.5".parse().unwrap();
+        assert_eq!(params.validate(), Err(ParamsError::InvalidFeeBurnFraction));
+        params.fee_burn_fraction = BigDecimal::from(-1);
+        assert_eq!(params.validate(), Err(ParamsError::InvalidFeeBurnFraction));
+    }
+
+    #[test]
+    fn test_adjustment_height() {
+        let params = ConsensusParams::default();
+        assert!(!params.is_adjustment_height(9));
+        assert!(params.is_adjustment_height(10));
+        assert!(params.is_adjustment_height(20));
+    }
+}
@@ -0,0 +1,236 @@
+use core::fmt;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use bigdecimal::BigDecimal;
+use serde::{Deserialize, Serialize};
+
+use crate::address::Address;
+use crate::block::{Block, BlockError};
+use crate::blockchain::Blockchain;
+use crate::consensus::ConsensusParams;
+use crate::transaction::Transaction;
+
+#[derive(Debug)]
+pub enum GenesisError {
+    Io(io::Error),
+    Parse(String),
+}
+
+impl From<io::Error> for GenesisError {
+    fn from(err: io::Error) -> GenesisError {
+        GenesisError::Io(err)
+    }
+}
+
+impl fmt::Display for GenesisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GenesisError::Io(err) => write!(f, "{}", err),
+            GenesisError::Parse(err) => write!(f, "invalid genesis file: {}", err),
+        }
+    }
+}
+
+/// Specification of a network, the genesis block included.
+///
+/// The genesis block only depends on this specification, so every node
+/// created from the same file gets the same genesis hash.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GenesisConfig {
+    pub chain_id: u64,
+    /// Creation time of the genesis block, as a UNIX timestamp.
+    pub timestamp: i64,
+    /// Target of the first block after the genesis one, in compact form.
+    pub initial_bits: u32,
+    /// Reward of the first mined block, see `ConsensusParams::get_block_reward`.
+    pub block_reward: BigDecimal,
+    pub halving_interval: u64,
+    pub max_supply: Option<BigDecimal>,
+    /// Part of the transaction fees burned instead of paid to the miner.
+    pub fee_burn_fraction: BigDecimal,
+    /// Initial balance of the accounts, by address.
+    pub allocations: BTreeMap<Address, BigDecimal>,
+}
+
+impl Default for GenesisConfig {
+    fn default() -> Self {
+        let params = ConsensusParams::default();
+        GenesisConfig {
+            chain_id: params.chain_id,
+            timestamp: 0,
+            initial_bits: params.initial_bits,
+            block_reward: params.block_reward,
+            halving_interval: params.halving_interval,
+            max_supply: params.max_supply,
+            fee_burn_fraction: params.fee_burn_fraction,
+            allocations: BTreeMap::new(),
+        }
+    }
+}
+
+impl GenesisConfig {
+    /// Loads a TOML file if its extension is `.toml`, a JSON one otherwise.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<GenesisConfig, GenesisError> {
+        let content = fs::read_to_string(&path)?;
+        let config: GenesisConfig = if is_toml(path.as_ref()) {
+            toml::from_str(&content).map_err(|err| GenesisError::Parse(err.to_string()))?
+        } else {
+            serde_json::from_str(&content).map_err(|err| GenesisError::Parse(err.to_string()))?
+        };
+        config
+            .params()
+            .validate()
+            .map_err(|err| GenesisError::Parse(err.to_string()))?;
+        Ok(config)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), GenesisError> {
+        let content = if is_toml(path.as_ref()) {
+            toml::to_string_pretty(self).map_err(|err| GenesisError::Parse(err.to_string()))?
+        } else {
+            serde_json::to_string_pretty(self)
+                .map_err(|err| GenesisError::Parse(err.to_string()))?
+        };
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    pub fn params(&self) -> ConsensusParams {
+        ConsensusParams {
+            chain_id: self.chain_id,
+            initial_bits: self.initial_bits,
+            block_reward: self.block_reward.clone(),
+            halving_interval: self.halving_interval,
+            max_supply: self.max_supply.clone(),
+            fee_burn_fraction: self.fee_burn_fraction.clone(),
+            ..ConsensusParams::default()
+        }
+    }
+
+    /// Genesis block holding one allocation per account, sorted by address.
+    pub fn genesis_block(&self) -> Result<Block, BlockError> {
+        if self.allocations.is_empty() {
+            return Err(BlockError::InvalidGenesis);
+        }
+        let txs: Vec<Transaction> = self
+            .allocations
+            .iter()
+            .map(|(address, value)| Transaction {
+                chain_id: self.chain_id,
+                ..Transaction::allocation(*address, value.clone(), self.timestamp)
+            })
+            .collect();
+        Block::genesis_at(&txs, self.timestamp)
+    }
+
+    pub fn blockchain(&self) -> Result<Blockchain, BlockError> {
+        Blockchain::new(self.genesis_block()?, self.params())
+    }
+}
+
+fn is_toml(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "toml")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::pow::POW_LIMIT_BITS;
+
+    fn get_test_config() -> GenesisConfig {
+        GenesisConfig {
+            chain_id: 42,
+            timestamp: 1_700_000_000,
+            initial_bits: POW_LIMIT_BITS,
+            block_reward: BigDecimal::from(50),
+            halving_interval: 100,
+            max_supply: Some(BigDecimal::from(10_000)),
+            fee_burn_fraction: "0.5".parse().unwrap(),
+            allocations: BTreeMap::from([
+                (Address::named("adel.eth"), BigDecimal::from(1000)),
+                (Address::named("bob.eth"), BigDecimal::from(20)),
+            ]),
+        }
+    }
+
+    #[test]
+    fn test_genesis_is_deterministic() {
+        let config = get_test_config();
+        let genesis = config.genesis_block().unwrap();
+        assert!(genesis.verify().is_ok());
+        assert_eq!(
+            genesis.block_hash,
+            config.genesis_block().unwrap().block_hash
+        );
+        assert_eq!(genesis.block_header.created_at, config.timestamp);
+
+        let blockchain = config.blockchain().unwrap();
+        assert_eq!(blockchain.params.chain_id, 42);
+        assert_eq!(blockchain.mining_bits, POW_LIMIT_BITS);
+        assert_eq!(blockchain.mining_reward, BigDecimal::from(50));
+        let state = &blockchain.state;
+        assert_eq!(
+            state.get_balance(&Address::named("adel.eth")),
+            BigDecimal::from(1000)
+        );
+        assert_eq!(state.get_total_supply(), BigDecimal::from(1020));
+        assert_eq!(state.get_nonce(&Address::named("adel.eth")), 0);
+
+        let mut other = get_test_config();
+        other
+            .allocations
+            .insert(Address::named("carl.eth"), 1.into());
+        assert_ne!(
+            other.genesis_block().unwrap().block_hash,
+            genesis.block_hash
+        );
+        assert_eq!(
+            GenesisConfig::default().genesis_block().err(),
+            Some(BlockError::InvalidGenesis)
+        );
+    }
+
+    #[test]
+    fn test_json_and_toml_files() {
+        let config = get_test_config();
+        let dir = env::temp_dir();
+        for name in ["genesis.json", "genesis.toml"] {
+            let path = dir.join(format!("bfs-{}-{}", std::process::id(), name));
+            config.save(&path).unwrap();
+            let loaded = GenesisConfig::load(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+            assert_eq!(loaded, config);
+            assert_eq!(
+                loaded.genesis_block().unwrap().block_hash,
+                config.genesis_block().unwrap().block_hash
+            );
+        }
+
+        let path = dir.join(format!("bfs-{}-partial.toml", std::process::id()));
+        let toml = format!(
+            "chain_id = 7\n[allocations]\n\"{}\" = \"5\"\n",
+            Address::named("adel.eth")
+        );
+        fs::write(&path, toml).unwrap();
+        let loaded = GenesisConfig::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.chain_id, 7);
+        assert_eq!(loaded.initial_bits, GenesisConfig::default().initial_bits);
+        assert_eq!(
+            loaded.allocations[&Address::named("adel.eth")],
+            BigDecimal::from(5)
+        );
+
+        fs::write(&path, "fee_burn_fraction = \"1.5\"\n").unwrap();
+        let loaded = GenesisConfig::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(loaded, Err(GenesisError::Parse(_))));
+    }
+}
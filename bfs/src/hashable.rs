@@ -1,5 +1,4 @@
 use serde::{Deserialize, Serialize};
-use serde_json::{from_str, Value};
 use std::{
     fs::{File, OpenOptions},
     io::Read,
@@ -42,8 +41,7 @@ pub trait Hashable: Serialize {
             .truncate(true)
             .open(new_file)
             .unwrap();
-        let json_str = self.to_json();
-        let json_value: Value = from_str(&json_str).unwrap();
-        serde_json::to_writer_pretty(file, &json_value).unwrap();
+        // Serialized directly so map entries (e.g. block txs) keep their order.
+        serde_json::to_writer_pretty(file, self).unwrap();
     }
 }
@@ -0,0 +1,129 @@
+use core::fmt;
+
+use bip32::{ChildNumber, DerivationPath, XPrv};
+use bip39::Mnemonic;
+
+use crate::wallet::Wallet;
+
+/// BIP-44 path of the external accounts, the coin type being Ethereum's one.
+pub const ACCOUNTS_DERIVATION_PATH: &str = "m/44'/60'/0'/0";
+pub const MNEMONIC_WORD_COUNT: usize = 24;
+
+#[derive(Debug)]
+pub enum HdWalletError {
+    InvalidMnemonic,
+    Derivation(bip32::Error),
+}
+
+impl From<bip32::Error> for HdWalletError {
+    fn from(err: bip32::Error) -> HdWalletError {
+        HdWalletError::Derivation(err)
+    }
+}
+
+impl fmt::Display for HdWalletError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HdWalletError::InvalidMnemonic => write!(f, "invalid mnemonic phrase"),
+            HdWalletError::Derivation(err) => write!(f, "key derivation failed: {}", err),
+        }
+    }
+}
+
+/// Wallets derived from a BIP-39 mnemonic phrase, one per account index, so
+/// that the same phrase always gives back the same accounts.
+pub struct HdWallet {
+    mnemonic: Mnemonic,
+    accounts_key: XPrv,
+}
+
+impl HdWallet {
+    /// New random phrase of `MNEMONIC_WORD_COUNT` words.
+    pub fn generate() -> HdWallet {
+        let mnemonic = Mnemonic::generate(MNEMONIC_WORD_COUNT).unwrap();
+        HdWallet::from_mnemonic(mnemonic, "").unwrap()
+    }
+
+    /// Imports an English phrase (12 to 24 words), along with its optional
+    /// BIP-39 passphrase.
+    pub fn from_phrase(phrase: &str, passphrase: &str) -> Result<HdWallet, HdWalletError> {
+        let mnemonic = Mnemonic::parse(phrase).map_err(|_| HdWalletError::InvalidMnemonic)?;
+        HdWallet::from_mnemonic(mnemonic, passphrase)
+    }
+
+    fn from_mnemonic(mnemonic: Mnemonic, passphrase: &str) -> Result<HdWallet, HdWalletError> {
+        let path: DerivationPath = ACCOUNTS_DERIVATION_PATH.parse()?;
+        let accounts_key = XPrv::derive_from_path(mnemonic.to_seed(passphrase), &path)?;
+        Ok(HdWallet {
+            mnemonic,
+            accounts_key,
+        })
+    }
+
+    pub fn phrase(&self) -> String {
+        self.mnemonic.to_string()
+    }
+
+    /// Wallet of the account at `index`, starting with a zero nonce.
+    pub fn account(&self, index: u32) -> Result<Wallet, HdWalletError> {
+        let child = self
+            .accounts_key
+            .derive_child(ChildNumber::new(index, false)?)?;
+        Ok(Wallet::from_private_key(child.private_key().clone(), 0))
+    }
+
+    /// Wallets of the first `count` accounts.
+    pub fn accounts(&self, count: u32) -> Result<Vec<Wallet>, HdWalletError> {
+        (0..count).map(|index| self.account(index)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_PHRASE: &str = "test test test test test test test test test test test junk";
+
+    #[test]
+    fn test_known_derivation() {
+        let hd_wallet = HdWallet::from_phrase(TEST_PHRASE, "").unwrap();
+        // First account of the usual development networks
+        assert_eq!(
+            hex::encode(hd_wallet.account(0).unwrap().private_key_bytes()),
+            "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+        );
+
+        let accounts = hd_wallet.accounts(3).unwrap();
+        let imported = HdWallet::from_phrase(&hd_wallet.phrase(), "").unwrap();
+        assert_eq!(
+            accounts[2].public_key(),
+            imported.account(2).unwrap().public_key()
+        );
+        assert_ne!(accounts[1].public_key(), accounts[2].public_key());
+
+        let with_passphrase = HdWallet::from_phrase(TEST_PHRASE, "secret").unwrap();
+        assert_ne!(
+            with_passphrase.account(0).unwrap().public_key(),
+            accounts[0].public_key()
+        );
+        assert!(hd_wallet.account(1 << 31).is_err());
+    }
+
+    #[test]
+    fn test_generate_and_import() {
+        let hd_wallet = HdWallet::generate();
+        assert_eq!(hd_wallet.phrase().split_whitespace().count(), 24);
+        let imported = HdWallet::from_phrase(&hd_wallet.phrase(), "").unwrap();
+        assert_eq!(
+            imported.account(0).unwrap().public_key(),
+            hd_wallet.account(0).unwrap().public_key()
+        );
+        assert!(matches!(
+            HdWallet::from_phrase(
+                "test test test test test test test test test test test test",
+                ""
+            ),
+            Err(HdWalletError::InvalidMnemonic)
+        ));
+    }
+}
@@ -0,0 +1,293 @@
+use core::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+
+use crate::address::Address;
+use crate::wallet::Wallet;
+
+pub const KEYSTORE_VERSION: u32 = 1;
+pub const KEYSTORE_CIPHER: &str = "aes-256-gcm";
+pub const KEYSTORE_KDF: &str = "scrypt";
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 32;
+const IV_LEN: usize = 12;
+/// Costliest scrypt parameters accepted, a keystore being untrusted input:
+/// 2^20 rounds with r = 8 take 1 GiB of memory.
+const MAX_KDF_LOG_N: u32 = 20;
+const MAX_KDF_R: u32 = 8;
+const MAX_KDF_P: u32 = 16;
+
+#[derive(Debug)]
+pub enum KeystoreError {
+    Io(io::Error),
+    InvalidFormat(String),
+    WrongPassword,
+}
+
+impl From<io::Error> for KeystoreError {
+    fn from(err: io::Error) -> KeystoreError {
+        KeystoreError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for KeystoreError {
+    fn from(err: serde_json::Error) -> KeystoreError {
+        KeystoreError::InvalidFormat(err.to_string())
+    }
+}
+
+impl fmt::Display for KeystoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeystoreError::Io(err) => write!(f, "{}", err),
+            KeystoreError::InvalidFormat(err) => write!(f, "invalid keystore: {}", err),
+            KeystoreError::WrongPassword => write!(f, "wrong password or corrupted keystore"),
+        }
+    }
+}
+
+/// Cost of deriving the encryption key from the password.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub dklen: usize,
+    /// CPU & memory cost, a power of 2.
+    pub n: u64,
+    pub r: u32,
+    pub p: u32,
+    pub salt: String,
+}
+
+impl Default for KdfParams {
+    /// 2^15 rounds, about 32 MiB of memory and a fraction of a second.
+    fn default() -> Self {
+        KdfParams {
+            dklen: KEY_LEN,
+            n: 1 << 15,
+            r: 8,
+            p: 1,
+            salt: String::new(),
+        }
+    }
+}
+
+impl KdfParams {
+    fn derive_key(&self, password: &str) -> Result<Vec<u8>, KeystoreError> {
+        if !self.n.is_power_of_two()
+            || self.n.trailing_zeros() > MAX_KDF_LOG_N
+            || self.r > MAX_KDF_R
+            || self.p > MAX_KDF_P
+            || self.dklen != KEY_LEN
+        {
+            return Err(KeystoreError::InvalidFormat(String::from(
+                "unsupported kdf parameters",
+            )));
+        }
+        let params = scrypt::Params::new(self.n.trailing_zeros() as u8, self.r, self.p, KEY_LEN)
+            .map_err(|err| KeystoreError::InvalidFormat(err.to_string()))?;
+        let salt = decode_hex(&self.salt)?;
+        let mut key = vec![0; KEY_LEN];
+        scrypt::scrypt(password.as_bytes(), &salt, &params, &mut key)
+            .map_err(|err| KeystoreError::InvalidFormat(err.to_string()))?;
+        Ok(key)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CipherParams {
+    pub iv: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Crypto {
+    pub cipher: String,
+    /// Encrypted private key, followed by the authentication tag.
+    pub ciphertext: String,
+    pub cipherparams: CipherParams,
+    pub kdf: String,
+    pub kdfparams: KdfParams,
+}
+
+/// Wallet encrypted with a password, laid out like the Ethereum keystores.
+///
+/// The address is authenticated along with the private key, so a keystore
+/// can't be edited to show another address. The nonce is kept in clear.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Keystore {
+    pub version: u32,
+    pub address: Address,
+    pub nonce: u64,
+    pub crypto: Crypto,
+}
+
+impl Keystore {
+    /// Encrypts the wallet with a key derived from `password`, using a fresh
+    /// salt & IV and the costs of `kdf_params`.
+    pub fn encrypt(
+        wallet: &Wallet,
+        password: &str,
+        kdf_params: &KdfParams,
+    ) -> Result<Keystore, KeystoreError> {
+        let mut salt = [0; SALT_LEN];
+        let mut iv = [0; IV_LEN];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut iv);
+        let kdfparams = KdfParams {
+            salt: hex::encode(salt),
+            ..kdf_params.clone()
+        };
+        let key = kdfparams.derive_key(password)?;
+        let address = wallet.address();
+        let ciphertext = Aes256Gcm::new_from_slice(&key)
+            .unwrap()
+            .encrypt(
+                &Nonce::from(iv),
+                Payload {
+                    msg: &wallet.private_key_bytes(),
+                    aad: address.as_bytes(),
+                },
+            )
+            .unwrap();
+        Ok(Keystore {
+            version: KEYSTORE_VERSION,
+            address,
+            nonce: wallet.nonce,
+            crypto: Crypto {
+                cipher: KEYSTORE_CIPHER.to_string(),
+                ciphertext: hex::encode(ciphertext),
+                cipherparams: CipherParams {
+                    iv: hex::encode(iv),
+                },
+                kdf: KEYSTORE_KDF.to_string(),
+                kdfparams,
+            },
+        })
+    }
+
+    pub fn decrypt(&self, password: &str) -> Result<Wallet, KeystoreError> {
+        if self.version != KEYSTORE_VERSION
+            || self.crypto.cipher != KEYSTORE_CIPHER
+            || self.crypto.kdf != KEYSTORE_KDF
+        {
+            return Err(KeystoreError::InvalidFormat(String::from(
+                "unsupported version, cipher or kdf",
+            )));
+        }
+        let iv: [u8; IV_LEN] = decode_hex(&self.crypto.cipherparams.iv)?
+            .try_into()
+            .map_err(|_| KeystoreError::InvalidFormat(String::from("invalid iv")))?;
+        let key = self.crypto.kdfparams.derive_key(password)?;
+        let private_key = Aes256Gcm::new_from_slice(&key)
+            .unwrap()
+            .decrypt(
+                &Nonce::from(iv),
+                Payload {
+                    msg: &decode_hex(&self.crypto.ciphertext)?,
+                    aad: self.address.as_bytes(),
+                },
+            )
+            .map_err(|_| KeystoreError::WrongPassword)?;
+        let wallet = Wallet::from_private_key_bytes(&private_key, self.nonce)
+            .ok_or_else(|| KeystoreError::InvalidFormat(String::from("invalid private key")))?;
+        Ok(wallet)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Keystore, KeystoreError> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), KeystoreError> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>, KeystoreError> {
+    hex::decode(hex).map_err(|err| KeystoreError::InvalidFormat(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    // Cheap enough for the tests, far too cheap for a real keystore.
+    fn get_test_params() -> KdfParams {
+        KdfParams {
+            n: 1 << 10,
+            ..KdfParams::default()
+        }
+    }
+
+    #[test]
+    fn test_encrypt_and_decrypt() {
+        let mut wallet = Wallet::new();
+        wallet.nonce = 7;
+        let keystore = Keystore::encrypt(&wallet, "hunter2", &get_test_params()).unwrap();
+        assert_eq!(keystore.address, wallet.address());
+        assert!(!keystore
+            .crypto
+            .ciphertext
+            .contains(&hex::encode(wallet.private_key_bytes())));
+
+        let decrypted = keystore.decrypt("hunter2").unwrap();
+        assert_eq!(decrypted.public_key(), wallet.public_key());
+        assert_eq!(decrypted.nonce, 7);
+        assert!(matches!(
+            keystore.decrypt("hunter3"),
+            Err(KeystoreError::WrongPassword)
+        ));
+
+        let mut tampered = keystore.clone();
+        tampered.address = Wallet::new().address();
+        assert!(matches!(
+            tampered.decrypt("hunter2"),
+            Err(KeystoreError::WrongPassword)
+        ));
+    }
+
+    #[test]
+    fn test_costly_kdf_params_are_rejected() {
+        let keystore = Keystore::encrypt(&Wallet::new(), "hunter2", &get_test_params()).unwrap();
+        for kdfparams in [
+            KdfParams {
+                n: 1 << 40,
+                ..keystore.crypto.kdfparams.clone()
+            },
+            KdfParams {
+                r: 1 << 20,
+                ..keystore.crypto.kdfparams.clone()
+            },
+            KdfParams {
+                p: 1 << 20,
+                ..keystore.crypto.kdfparams.clone()
+            },
+        ] {
+            let mut costly = keystore.clone();
+            costly.crypto.kdfparams = kdfparams;
+            assert!(matches!(
+                costly.decrypt("hunter2"),
+                Err(KeystoreError::InvalidFormat(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = env::temp_dir().join(format!("bfs-keystore-{}.json", std::process::id()));
+        let wallet = Wallet::new();
+        Keystore::encrypt(&wallet, "hunter2", &get_test_params())
+            .unwrap()
+            .save(&path)
+            .unwrap();
+        let loaded = Keystore::load(&path).unwrap().decrypt("hunter2").unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.public_key(), wallet.public_key());
+    }
+}
@@ -0,0 +1,385 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+
+use bigdecimal::{BigDecimal, Zero};
+
+use crate::address::Address;
+use crate::block::Block;
+use crate::hashable::Hashable;
+use crate::state::State;
+use crate::transaction::Transaction;
+
+pub const MEMPOOL_DEFAULT_MAX_SIZE: usize = 10_000;
+
+#[derive(Debug, PartialEq)]
+pub enum MempoolError {
+    AlreadyKnown,
+    NegativeAmount,
+    InvalidSignature,
+    WrongChain { expected: u64, got: u64 },
+    NonceTooLow { expected: u64, got: u64 },
+    InsufficientBalance,
+    ReplacementUnderpriced,
+    PoolFull,
+}
+
+/// Signed transactions waiting to be included in a block.
+///
+/// Transactions are kept per sender ordered by nonce, so transactions with a
+/// nonce ahead of the sender state wait until the gap is filled.
+pub struct Mempool {
+    txs: HashMap<String, Transaction>,
+    by_sender: HashMap<Address, BTreeMap<u64, String>>,
+    pub max_size: usize,
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Next executable transaction of a sender, ordered by fee then by hash.
+struct Candidate<'a> {
+    tx_hash: &'a String,
+    tx: &'a Transaction,
+}
+
+impl Ord for Candidate<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.tx
+            .fee
+            .cmp(&other.tx.fee)
+            .then_with(|| other.tx_hash.cmp(self.tx_hash))
+    }
+}
+
+impl PartialOrd for Candidate<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Candidate<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.tx_hash == other.tx_hash
+    }
+}
+
+impl Eq for Candidate<'_> {}
+
+impl Mempool {
+    pub fn new() -> Mempool {
+        Mempool {
+            txs: HashMap::new(),
+            by_sender: HashMap::new(),
+            max_size: MEMPOOL_DEFAULT_MAX_SIZE,
+        }
+    }
+
+    /// Validates a transaction against the state and adds it to the pool.
+    ///
+    /// A transaction with the same sender & nonce as a pending one replaces it
+    /// only if it pays a higher fee.
+    pub fn add(&mut self, tx: Transaction, state: &State) -> Result<String, MempoolError> {
+        let tx_hash = tx.get_hash();
+        if self.txs.contains_key(&tx_hash) {
+            return Err(MempoolError::AlreadyKnown);
+        }
+        if tx.value < BigDecimal::zero() || tx.fee < BigDecimal::zero() {
+            return Err(MempoolError::NegativeAmount);
+        }
+        if tx.chain_id != state.chain_id {
+            return Err(MempoolError::WrongChain {
+                expected: state.chain_id,
+                got: tx.chain_id,
+            });
+        }
+        if !tx.is_correctly_signed(state.chain_id) {
+            return Err(MempoolError::InvalidSignature);
+        }
+        let account = state.get_account(&tx.from);
+        if tx.nonce < account.nonce {
+            return Err(MempoolError::NonceTooLow {
+                expected: account.nonce,
+                got: tx.nonce,
+            });
+        }
+        let replaced = self.get_by_sender_nonce(&tx.from, tx.nonce).cloned();
+        if let Some(replaced) = &replaced {
+            if tx.fee <= replaced.fee {
+                return Err(MempoolError::ReplacementUnderpriced);
+            }
+        } else if self.txs.len() >= self.max_size {
+            return Err(MempoolError::PoolFull);
+        }
+        // Everything pending up to this nonce must remain affordable.
+        let pending_spent: BigDecimal = self
+            .get_sender_txs(&tx.from)
+            .filter(|pending| pending.nonce < tx.nonce)
+            .map(|pending| pending.get_cost())
+            .sum();
+        if &account.balance - pending_spent - tx.get_cost() <= BigDecimal::zero() {
+            return Err(MempoolError::InsufficientBalance);
+        }
+
+        if let Some(replaced) = replaced {
+            self.remove(&replaced.get_hash());
+        }
+        self.by_sender
+            .entry(tx.from)
+            .or_default()
+            .insert(tx.nonce, tx_hash.clone());
+        self.txs.insert(tx_hash.clone(), tx);
+        Ok(tx_hash)
+    }
+
+    pub fn remove(&mut self, tx_hash: &str) -> Option<Transaction> {
+        let tx = self.txs.remove(tx_hash)?;
+        if let Some(sender_txs) = self.by_sender.get_mut(&tx.from) {
+            sender_txs.remove(&tx.nonce);
+            if sender_txs.is_empty() {
+                self.by_sender.remove(&tx.from);
+            }
+        }
+        Some(tx)
+    }
+
+    /// Drops the transactions included in a new block, and every transaction
+    /// made stale by it (nonce already used by its sender).
+    pub fn remove_block_txs(&mut self, block: &Block, state: &State) {
+        for tx_hash in block.txs.keys() {
+            self.remove(tx_hash);
+        }
+        let stale: Vec<String> = self
+            .txs
+            .iter()
+            .filter(|(_, tx)| tx.nonce < state.get_account(&tx.from).nonce)
+            .map(|(tx_hash, _)| tx_hash.clone())
+            .collect();
+        for tx_hash in stale {
+            self.remove(&tx_hash);
+        }
+    }
+
+    /// Takes back the transactions of blocks removed from the active chain,
+    /// dropping those already mined again or no longer valid.
+    pub fn reinject(&mut self, txs: Vec<Transaction>, state: &State) {
+        for tx in txs {
+            let _ = self.add(tx, state);
+        }
+    }
+
+    /// Picks up to `max_txs` executable transactions, highest fee first,
+    /// while keeping each sender transactions in nonce order without gaps.
+    pub fn select(&self, state: &State, max_txs: usize) -> Vec<Transaction> {
+        let mut next_nonces: HashMap<&Address, u64> = HashMap::new();
+        let mut candidates = BinaryHeap::new();
+        for sender in self.by_sender.keys() {
+            let nonce = state.get_account(sender).nonce;
+            next_nonces.insert(sender, nonce);
+            if let Some(candidate) = self.get_candidate(sender, nonce) {
+                candidates.push(candidate);
+            }
+        }
+        let mut selected = Vec::new();
+        while selected.len() < max_txs {
+            let Some(candidate) = candidates.pop() else {
+                break;
+            };
+            selected.push(candidate.tx.clone());
+            let next_nonce = next_nonces.get_mut(&candidate.tx.from).unwrap();
+            *next_nonce += 1;
+            if let Some(next) = self.get_candidate(&candidate.tx.from, *next_nonce) {
+                candidates.push(next);
+            }
+        }
+        selected
+    }
+
+    fn get_candidate(&self, sender: &Address, nonce: u64) -> Option<Candidate<'_>> {
+        let tx_hash = self.by_sender.get(sender)?.get(&nonce)?;
+        Some(Candidate {
+            tx_hash,
+            tx: self.txs.get(tx_hash)?,
+        })
+    }
+
+    fn get_by_sender_nonce(&self, sender: &Address, nonce: u64) -> Option<&Transaction> {
+        self.get_candidate(sender, nonce)
+            .map(|candidate| candidate.tx)
+    }
+
+    /// Pending transactions of a sender, in nonce order.
+    pub fn get_sender_txs<'a>(&'a self, sender: &Address) -> impl Iterator<Item = &'a Transaction> {
+        self.by_sender
+            .get(sender)
+            .into_iter()
+            .flat_map(|sender_txs| sender_txs.values())
+            .filter_map(|tx_hash| self.txs.get(tx_hash))
+    }
+
+    pub fn get(&self, tx_hash: &str) -> Option<&Transaction> {
+        self.txs.get(tx_hash)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Transaction)> {
+        self.txs.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.txs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.txs.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallet::Wallet;
+
+    fn get_test_state(wallets: &[&Wallet]) -> State {
+        let txs: Vec<Transaction> = wallets
+            .iter()
+            .map(|wallet| Transaction::allocation(wallet.address(), 100_000.into(), 0))
+            .collect();
+        State::from_genesis(&Block::genesis(&txs).unwrap()).unwrap()
+    }
+
+    fn send(wallet: &mut Wallet, value: u64, fee: u64) -> Transaction {
+        let tx = Transaction::new(
+            wallet.address(),
+            Address::named("adel.eth"),
+            BigDecimal::from(value),
+            BigDecimal::from(fee),
+            wallet.nonce,
+        );
+        wallet.nonce += 1;
+        wallet.sign(tx)
+    }
+
+    #[test]
+    fn test_add_validates_against_state() {
+        let mut wallet = Wallet::new();
+        let mut state = get_test_state(&[&wallet]);
+        // The first transaction of the wallet is already mined
+        let mined = send(&mut wallet, 1, 0);
+        state.apply_tx(&mined).unwrap();
+        let mut mempool = Mempool::new();
+
+        let tx = send(&mut wallet, 10, 1);
+        assert!(mempool.add(tx.clone(), &state).is_ok());
+        assert_eq!(mempool.add(tx, &state), Err(MempoolError::AlreadyKnown));
+
+        let mut tampered = send(&mut wallet, 10, 1);
+        tampered.value = BigDecimal::from(1);
+        assert_eq!(
+            mempool.add(tampered, &state),
+            Err(MempoolError::InvalidSignature)
+        );
+
+        let mut other_chain = send(&mut wallet, 10, 1);
+        other_chain.chain_id = 1337;
+        assert_eq!(
+            mempool.add(wallet.sign(other_chain), &state),
+            Err(MempoolError::WrongChain {
+                expected: state.chain_id,
+                got: 1337
+            })
+        );
+
+        wallet.nonce = 0;
+        assert_eq!(
+            mempool.add(send(&mut wallet, 10, 1), &state),
+            Err(MempoolError::NonceTooLow {
+                expected: 1,
+                got: 0
+            })
+        );
+
+        wallet.nonce = 2;
+        assert_eq!(
+            mempool.add(send(&mut wallet, 99_990, 1), &state),
+            Err(MempoolError::InsufficientBalance)
+        );
+        assert_eq!(mempool.len(), 1);
+    }
+
+    #[test]
+    fn test_add_rejects_negative_amounts() {
+        let mut wallet = Wallet::new();
+        let state = get_test_state(&[&wallet]);
+        let mut mempool = Mempool::new();
+
+        let tx = wallet.send(Address::named("adel.eth"), BigDecimal::from(-1000));
+        assert_eq!(mempool.add(tx, &state), Err(MempoolError::NegativeAmount));
+        let tx = wallet.send_with_fee(
+            Address::named("adel.eth"),
+            BigDecimal::from(1),
+            BigDecimal::from(-1),
+        );
+        assert_eq!(mempool.add(tx, &state), Err(MempoolError::NegativeAmount));
+        assert!(mempool.is_empty());
+    }
+
+    #[test]
+    fn test_add_accepts_self_transfers() {
+        let mut wallet = Wallet::new();
+        let mut state = get_test_state(&[&wallet]);
+        let mut mempool = Mempool::new();
+
+        // Valid on chain, so relayed like any other transfer
+        let tx = wallet.send_with_fee(wallet.address(), BigDecimal::from(5), BigDecimal::from(1));
+        assert!(mempool.add(tx.clone(), &state).is_ok());
+        assert!(state.apply_tx(&tx).is_ok());
+    }
+
+    #[test]
+    fn test_replace_by_fee() {
+        let mut wallet = Wallet::new();
+        let state = get_test_state(&[&wallet]);
+        let mut mempool = Mempool::new();
+
+        mempool.add(send(&mut wallet, 10, 2), &state).unwrap();
+        wallet.nonce -= 1;
+        assert_eq!(
+            mempool.add(send(&mut wallet, 20, 2), &state),
+            Err(MempoolError::ReplacementUnderpriced)
+        );
+        wallet.nonce -= 1;
+        let replacing = mempool.add(send(&mut wallet, 10, 3), &state).unwrap();
+        assert_eq!(mempool.len(), 1);
+        assert!(mempool.get(&replacing).is_some());
+    }
+
+    #[test]
+    fn test_select_orders_by_fee_and_nonce() {
+        let mut wallet_a = Wallet::new();
+        let mut wallet_b = Wallet::new();
+        let state = get_test_state(&[&wallet_a, &wallet_b]);
+        let mut mempool = Mempool::new();
+
+        let a1 = send(&mut wallet_a, 1, 1);
+        let a2 = send(&mut wallet_a, 1, 10);
+        let b1 = send(&mut wallet_b, 1, 5);
+        let b3 = {
+            wallet_b.nonce += 1;
+            send(&mut wallet_b, 1, 100)
+        };
+        for tx in [a2.clone(), b3, a1.clone(), b1.clone()] {
+            mempool.add(tx, &state).unwrap();
+        }
+
+        let selected: Vec<String> = mempool
+            .select(&state, 10)
+            .iter()
+            .map(|tx| tx.get_hash())
+            .collect();
+        // b3 waits for the missing nonce, a2 can't go before a1
+        assert_eq!(selected, vec![b1.get_hash(), a1.get_hash(), a2.get_hash()]);
+        assert_eq!(mempool.select(&state, 1).len(), 1);
+    }
+}
@@ -1,5 +1,4 @@
 use core::fmt;
-use std::cmp::{max, min};
 use std::collections::HashMap;
 
 use serde::{Deserialize, Serialize};
@@ -8,6 +7,69 @@ use crate::hashable::Hashable;
 use crate::transaction::Transaction;
 use crate::utils::to_readable_hash;
 
+/// Hash to combine with at one level of a `MerkleProof`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MerkleProofStep {
+    pub hash: String,
+    /// Whether the sibling comes first when hashing the pair.
+    pub is_left: bool,
+}
+
+/// Path from a transaction hash up to the Merkle root, enough to prove the
+/// transaction is part of a block knowing only its header.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub tx_hash: String,
+    /// Siblings from the leaves up to the root.
+    pub siblings: Vec<MerkleProofStep>,
+}
+
+impl MerkleProof {
+    pub fn get_root(&self) -> String {
+        self.siblings
+            .iter()
+            .fold(self.tx_hash.clone(), |hash, sibling| {
+                match sibling.is_left {
+                    true => sha256::digest(sibling.hash.clone() + &hash),
+                    false => sha256::digest(hash + &sibling.hash),
+                }
+            })
+    }
+
+    /// Position of the transaction in the block, given by the sides of its
+    /// siblings.
+    pub fn get_index(&self) -> u64 {
+        self.siblings
+            .iter()
+            .rev()
+            .fold(0, |index, sibling| index * 2 + sibling.is_left as u64)
+    }
+
+    /// Checks the proof against the Merkle root of a block of `txs_number`
+    /// transactions, i.e. `BlockHeader.hash` and `BlockHeader.txs_number`.
+    ///
+    /// Leaves and inner nodes are hashed alike, so the path must go down to
+    /// the leaves of such a tree: a shorter one could pass an inner node off
+    /// as a transaction.
+    pub fn verify(&self, root: &str, txs_number: u64) -> bool {
+        self.siblings.len() == get_depth(txs_number)
+            && self.get_index() < txs_number
+            && self.get_root() == root
+    }
+}
+
+/// Number of levels above the leaves of the tree of `txs_number` transactions.
+fn get_depth(txs_number: u64) -> usize {
+    // Leaves are padded to an even number, see `MerkleTree::new`
+    let mut width = txs_number + txs_number % 2;
+    let mut depth = 0;
+    while width > 1 {
+        width = width.div_ceil(2);
+        depth += 1;
+    }
+    depth
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct MerkleTree {
     pub mt: HashMap<usize, Vec<String>>,
@@ -17,7 +79,7 @@ impl MerkleTree {
     pub fn new(txs: &[Transaction]) -> Self {
         let mut merkle_tree = MerkleTree { mt: HashMap::new() };
         let mut leafs: Vec<String> = txs.iter().map(|tx| tx.get_hash()).collect();
-        if leafs.len() % 2 != 0 {
+        if !leafs.len().is_multiple_of(2) {
             leafs.push(sha256::digest(leafs.last().unwrap().clone()));
         }
         merkle_tree.mt.insert(1, leafs.clone());
@@ -47,37 +109,32 @@ impl MerkleTree {
         self
     }
 
-    fn get_index_of_hash(&self, height_hashes: &[String], searched_hash: &String) -> Option<usize> {
-        height_hashes.iter().position(|r| r == searched_hash)
-    }
-
-    fn hash_is_in(&self, hash: String) -> bool {
-        let mut curr_hash = hash.clone();
-        for curr_height in 1..self.len() {
-            let curr_height_hashes = self.get_height_hashes(curr_height);
-            let curr_index = match self.get_index_of_hash(curr_height_hashes, &curr_hash) {
-                Option::Some(idx) => idx,
-                Option::None => return false,
-            };
-
-            let neighbour_position = if curr_index % 2 == 0 {
-                min(curr_index + 1, curr_height_hashes.len() - 1)
-            } else {
-                max(curr_index - 1, 0)
-            };
-            let neighbour_hash = curr_height_hashes[neighbour_position].clone();
-
-            curr_hash = if curr_index % 2 == 0 {
-                sha256::digest(curr_hash + &neighbour_hash)
-            } else {
-                sha256::digest(neighbour_hash + &curr_hash)
-            };
+    /// Proof of inclusion of a transaction hash, `None` if it is not a leaf.
+    pub fn get_proof(&self, tx_hash: &str) -> Option<MerkleProof> {
+        let mut index = self
+            .get_height_hashes(1)
+            .iter()
+            .position(|h| h == tx_hash)?;
+        let mut siblings = Vec::new();
+        for height in 1..self.len() {
+            let hashes = self.get_height_hashes(height);
+            // The last hash of a level of odd length is paired with itself
+            let sibling_index = (index ^ 1).min(hashes.len() - 1);
+            siblings.push(MerkleProofStep {
+                hash: hashes[sibling_index].clone(),
+                is_left: index % 2 == 1,
+            });
+            index /= 2;
         }
-        curr_hash == self.get_root()
+        Some(MerkleProof {
+            tx_hash: tx_hash.to_string(),
+            siblings,
+        })
     }
 
     pub fn tx_is_in(&self, tx: &Transaction) -> bool {
-        self.hash_is_in(tx.get_hash())
+        self.get_proof(&tx.get_hash())
+            .is_some_and(|proof| proof.get_root() == self.get_root())
     }
 
     pub fn get_height_hashes(&self, depth: usize) -> &[String] {
@@ -124,6 +181,7 @@ mod tests {
     use bigdecimal::BigDecimal;
 
     use super::*;
+    use crate::address::Address;
     use crate::{transaction::Transaction, utils::get_rand_txs};
 
     #[test]
@@ -157,8 +215,8 @@ mod tests {
     fn test_tx_is_contained_in_tree() {
         let mut txs = get_rand_txs(1000);
         let tx = Transaction::new(
-            String::from("aihe.eth"),
-            String::from("adel.eth"),
+            Address::named("aihe.eth"),
+            Address::named("adel.eth"),
             BigDecimal::from(3000),
             BigDecimal::from(1),
             0,
@@ -169,12 +227,58 @@ mod tests {
         assert!(merkle_tree.tx_is_in(&tx));
 
         let tx_not_inside = Transaction::new(
-            String::from("adel.eth"),
-            String::from("aihe.eth"),
+            Address::named("adel.eth"),
+            Address::named("aihe.eth"),
             BigDecimal::from(3000),
             BigDecimal::from(1),
             0,
         );
         assert!(!merkle_tree.tx_is_in(&tx_not_inside));
     }
+
+    #[test]
+    fn test_proofs_are_verified_against_the_root() {
+        for nb_txs in [1, 2, 5, 6, 13] {
+            let txs = get_rand_txs(nb_txs);
+            let merkle_tree = MerkleTree::new(&txs);
+            let root = merkle_tree.get_root();
+            for (i, tx) in txs.iter().enumerate() {
+                let proof = merkle_tree.get_proof(&tx.get_hash()).unwrap();
+                assert_eq!(proof.siblings.len(), merkle_tree.len() - 1);
+                assert_eq!(proof.get_index(), i as u64);
+                assert!(proof.verify(&root, nb_txs as u64));
+            }
+        }
+
+        let txs = get_rand_txs(6);
+        let merkle_tree = MerkleTree::new(&txs);
+        let mut proof = merkle_tree.get_proof(&txs[3].get_hash()).unwrap();
+        assert!(merkle_tree.get_proof("unknown").is_none());
+        proof.siblings[1].is_left = !proof.siblings[1].is_left;
+        assert!(!proof.verify(&merkle_tree.get_root(), 6));
+        proof.tx_hash = txs[2].get_hash();
+        assert!(!proof.verify(&merkle_tree.get_root(), 6));
+    }
+
+    #[test]
+    fn test_proofs_of_inner_nodes_are_rejected() {
+        let txs = get_rand_txs(5);
+        let merkle_tree = MerkleTree::new(&txs);
+        let root = merkle_tree.get_root();
+
+        // Hashing the node of the first two txs gives the root as well
+        let proof = merkle_tree.get_proof(&txs[0].get_hash()).unwrap();
+        let inner = MerkleProof {
+            tx_hash: merkle_tree.get_height_hashes(2)[0].clone(),
+            siblings: proof.siblings[1..].to_vec(),
+        };
+        assert_eq!(inner.get_root(), root);
+        assert!(!inner.verify(&root, 5));
+
+        // Nor is the leaf padding the odd number of txs
+        let padding = merkle_tree.get_height_hashes(1)[5].clone();
+        let proof = merkle_tree.get_proof(&padding).unwrap();
+        assert_eq!(proof.get_root(), root);
+        assert!(!proof.verify(&root, 5));
+    }
 }
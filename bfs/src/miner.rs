@@ -1,31 +1,100 @@
+use core::fmt;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
 use bigdecimal::BigDecimal;
 
 use crate::{
+    address::Address,
     block::{block_header::MiningBlockHeader, BlockHeader},
+    block_template::{BlockLimits, BlockTemplate},
     blockchain::Blockchain,
+    mempool::Mempool,
     merkle_tree::MerkleTree,
+    mining_session::MiningSession,
     transaction::Transaction,
     wallet::Wallet,
 };
 
 pub const MINING_DEFAULT_ATTEMPS: u64 = 100;
+pub const MINING_DEFAULT_THREADS: usize = 1;
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum MiningError {
     UnsuccessfulMining,
+    InvalidProofOfWork,
+    InvalidBlockNumber {
+        expected: u64,
+        got: u64,
+    },
+    InvalidPrevHash,
+    InvalidDifficulty {
+        expected: u32,
+        got: u32,
+    },
+    InvalidReward {
+        expected: BigDecimal,
+        got: BigDecimal,
+    },
+    InvalidTimestamp,
+    Cancelled,
+}
+
+#[derive(Debug, Clone)]
+pub struct MiningStats {
+    pub hashes_per_worker: Vec<u64>,
+    pub elapsed: Duration,
+}
+
+impl MiningStats {
+    pub fn hashes(&self) -> u64 {
+        self.hashes_per_worker.iter().sum()
+    }
+
+    /// Hashes computed per second, all workers included.
+    pub fn hashrate(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        self.hashes() as f64 / secs
+    }
+}
+
+impl fmt::Display for MiningStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "⚡ {:.0} H/s [{} hashes in {:.2}s on {} threads]",
+            self.hashrate(),
+            self.hashes(),
+            self.elapsed.as_secs_f64(),
+            self.hashes_per_worker.len()
+        )
+    }
 }
 
 pub struct Miner {
     pub wallet: Wallet,
+    /// Number of worker threads the nonce space is split across.
+    pub nb_threads: usize,
+    /// Statistics of the last mining attempt, successful or not.
+    pub last_stats: Option<MiningStats>,
 }
 
 impl Miner {
     pub fn new(wallet: Wallet) -> Miner {
-        Miner { wallet }
+        Miner {
+            wallet,
+            nb_threads: MINING_DEFAULT_THREADS,
+            last_stats: None,
+        }
     }
 
-    pub fn get_pub_key(&self) -> String {
-        self.wallet.public_key.to_encoded_point(true).to_string()
+    pub fn get_address(&self) -> Address {
+        self.wallet.address()
     }
 
     pub fn mine(
@@ -33,42 +102,80 @@ impl Miner {
         txs: &[Transaction],
         prev_header: &BlockHeader,
         prev_block_hash: &str,
-        difficulty: u64,
+        bits: u32,
         reward: &BigDecimal,
         attempts: u64,
     ) -> Result<MiningBlockHeader, MiningError> {
+        let bh = self.build_header(txs, prev_header, prev_block_hash, bits, reward);
+        let (header_mined, stats) = search_nonce(
+            &bh,
+            0..attempts,
+            self.nb_threads.max(1) as u64,
+            &AtomicBool::new(false),
+        );
+        self.last_stats = Some(stats);
+        header_mined.ok_or(MiningError::UnsuccessfulMining)
+    }
+
+    fn build_header(
+        &self,
+        txs: &[Transaction],
+        prev_header: &BlockHeader,
+        prev_block_hash: &str,
+        bits: u32,
+        reward: &BigDecimal,
+    ) -> MiningBlockHeader {
         let mt = MerkleTree::new(txs);
-        let mut bh = MiningBlockHeader::new(
+        MiningBlockHeader::new(
             &mt.get_root(),
             prev_block_hash,
             prev_header.block_number + 1,
             txs.len() as u64,
-            difficulty,
+            bits,
             reward.clone(),
-            self.get_pub_key(),
+            self.get_address(),
+        )
+    }
+
+    /// Starts a mining session on top of the current tip of the blockchain.
+    pub fn start_session(&self, blockchain: &Blockchain, txs: &[Transaction]) -> MiningSession {
+        let last_block = blockchain.get_last_block();
+        let bh = self.build_header(
+            txs,
+            &last_block.block_header,
+            &last_block.block_hash,
+            blockchain.mining_bits,
+            &blockchain.mining_reward,
         );
-        for _ in 0..attempts {
-            if bh.is_pow_computation_valid() {
-                break;
-            }
-            bh.nonce += 1;
-        }
-        if bh.nonce >= attempts {
-            return Err(MiningError::UnsuccessfulMining);
+        MiningSession::new(bh, self.nb_threads)
+    }
+
+    /// Coinbase of an empty block on top of the tip of the blockchain.
+    pub fn build_coinbase(&self, blockchain: &Blockchain) -> Transaction {
+        let block_number = blockchain.get_last_block().block_header.block_number + 1;
+        Transaction {
+            chain_id: blockchain.params.chain_id,
+            ..Transaction::coinbase(
+                self.get_address(),
+                blockchain.mining_reward.clone(),
+                block_number,
+            )
         }
-        Ok(bh)
     }
 
-    pub fn sign_coinbase(&mut self, reward: &BigDecimal) -> Transaction {
-        let tx = self.wallet.sign(Transaction::new(
-            self.get_pub_key(),
-            self.get_pub_key(),
-            reward.clone(),
-            BigDecimal::from(0),
-            self.wallet.nonce,
-        ));
-        self.wallet.nonce += 1;
-        tx
+    /// Template of the next block: a coinbase paying this miner followed by
+    /// the best transactions of the pool fitting in `limits`.
+    pub fn build_template(
+        &self,
+        blockchain: &Blockchain,
+        mempool: &Mempool,
+        limits: &BlockLimits,
+    ) -> BlockTemplate {
+        BlockTemplate::build(blockchain, mempool, &self.get_address(), limits)
+    }
+
+    pub fn start_template_session(&self, template: &BlockTemplate) -> MiningSession {
+        MiningSession::new(template.header.clone(), self.nb_threads)
     }
 
     pub fn mine_next_block(
@@ -83,10 +190,122 @@ impl Miner {
             txs,
             &last_block.block_header,
             &last_block.block_hash,
-            blockchain.mining_difficulty,
+            blockchain.mining_bits,
             &blockchain.mining_reward,
             attempts,
         )?;
         Ok(header_mined)
     }
 }
+
+/// Tries the `nonces` of the header, worker `i` of `nb_workers` trying
+/// `start + i`, `start + i + nb_workers`... All the workers stop as soon as
+/// one of them finds a valid nonce or `cancelled` is set.
+pub(crate) fn search_nonce(
+    header: &MiningBlockHeader,
+    nonces: Range<u64>,
+    nb_workers: u64,
+    cancelled: &AtomicBool,
+) -> (Option<MiningBlockHeader>, MiningStats) {
+    let found = AtomicBool::new(false);
+    let start = Instant::now();
+    let results: Vec<(u64, Option<MiningBlockHeader>)> = thread::scope(|s| {
+        let workers: Vec<_> = (0..nb_workers)
+            .map(|worker| {
+                let mut bh = header.clone();
+                let found = &found;
+                let Range { start, end } = nonces;
+                s.spawn(move || {
+                    let mut hashes = 0;
+                    bh.nonce = match start.checked_add(worker) {
+                        Some(nonce) => nonce,
+                        None => return (hashes, None),
+                    };
+                    while bh.nonce < end
+                        && !found.load(Ordering::Relaxed)
+                        && !cancelled.load(Ordering::Relaxed)
+                    {
+                        hashes += 1;
+                        if bh.is_pow_computation_valid() {
+                            found.store(true, Ordering::Relaxed);
+                            return (hashes, Some(bh));
+                        }
+                        bh.nonce = match bh.nonce.checked_add(nb_workers) {
+                            Some(nonce) => nonce,
+                            None => break,
+                        };
+                    }
+                    (hashes, None)
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .collect()
+    });
+    let stats = MiningStats {
+        hashes_per_worker: results.iter().map(|(hashes, _)| *hashes).collect(),
+        elapsed: start.elapsed(),
+    };
+    let header_mined = results
+        .into_iter()
+        .filter_map(|(_, header)| header)
+        .min_by_key(|header| header.nonce);
+    (header_mined, stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+
+    fn get_test_txs(miner: &mut Miner) -> (Vec<Transaction>, Block) {
+        let wallet = Wallet::new();
+        let genesis =
+            Block::genesis(&[Transaction::allocation(wallet.address(), 100_000.into(), 0)])
+                .unwrap();
+        let coinbase = Transaction::coinbase(miner.get_address(), BigDecimal::from(1), 1);
+        (vec![coinbase], genesis)
+    }
+
+    #[test]
+    fn test_parallel_mining_finds_valid_header() {
+        let mut miner = Miner::new(Wallet::new());
+        miner.nb_threads = 4;
+        let (txs, genesis) = get_test_txs(&mut miner);
+        let header = miner
+            .mine(
+                &txs,
+                &genesis.block_header,
+                &genesis.block_hash,
+                crate::pow::BASE_MINING_BITS,
+                &BigDecimal::from(1),
+                u64::MAX,
+            )
+            .unwrap();
+        assert!(header.is_pow_computation_valid());
+        let stats = miner.last_stats.unwrap();
+        assert_eq!(stats.hashes_per_worker.len(), 4);
+        assert!(stats.hashes() > 0);
+    }
+
+    #[test]
+    fn test_parallel_mining_splits_attempts() {
+        let mut miner = Miner::new(Wallet::new());
+        miner.nb_threads = 3;
+        let (txs, genesis) = get_test_txs(&mut miner);
+        let result = miner.mine(
+            &txs,
+            &genesis.block_header,
+            &genesis.block_hash,
+            // Target of 1, no hash meets it
+            0x03000001,
+            &BigDecimal::from(1),
+            100,
+        );
+        assert_eq!(result.unwrap_err(), MiningError::UnsuccessfulMining);
+        let stats = miner.last_stats.unwrap();
+        assert_eq!(stats.hashes_per_worker, vec![34, 33, 33]);
+    }
+}
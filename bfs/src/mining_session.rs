@@ -0,0 +1,227 @@
+use core::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+
+use crate::block::block_header::MiningBlockHeader;
+use crate::miner::{search_nonce, MiningError};
+
+/// Number of nonces tried between two progress reports.
+pub const MINING_PROGRESS_INTERVAL: u64 = 10_000;
+
+/// Shared flag used to stop a mining session from another thread,
+/// typically when a new tip arrives and the header being mined is stale.
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MiningProgress {
+    /// Nonces tried since the session started.
+    pub attempts: u64,
+    /// Hashes per second since the session started.
+    pub hashrate: f64,
+    pub elapsed: Duration,
+    /// Next nonce to be tried.
+    pub nonce: u64,
+    pub created_at: i64,
+}
+
+impl fmt::Display for MiningProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "⏳ {} attempts in {:.2}s [⚡ {:.0} H/s]",
+            self.attempts,
+            self.elapsed.as_secs_f64(),
+            self.hashrate
+        )
+    }
+}
+
+/// Mining of a single header template that can be run in several calls,
+/// each one resuming from the last nonce tried, and cancelled at any time.
+///
+/// Once every nonce has been tried, `created_at` is rolled forward and the
+/// search starts again from nonce 0.
+pub struct MiningSession {
+    header: MiningBlockHeader,
+    next_nonce: u64,
+    attempts: u64,
+    elapsed: Duration,
+    nb_threads: usize,
+    cancellation: CancellationToken,
+}
+
+impl MiningSession {
+    pub fn new(header: MiningBlockHeader, nb_threads: usize) -> MiningSession {
+        MiningSession {
+            next_nonce: header.nonce,
+            header,
+            attempts: 0,
+            elapsed: Duration::ZERO,
+            nb_threads: nb_threads.max(1),
+            cancellation: CancellationToken::new(),
+        }
+    }
+
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+
+    pub fn header(&self) -> &MiningBlockHeader {
+        &self.header
+    }
+
+    pub fn get_progress(&self) -> MiningProgress {
+        let secs = self.elapsed.as_secs_f64();
+        MiningProgress {
+            attempts: self.attempts,
+            hashrate: if secs > 0.0 {
+                self.attempts as f64 / secs
+            } else {
+                0.0
+            },
+            elapsed: self.elapsed,
+            nonce: self.next_nonce,
+            created_at: self.header.created_at,
+        }
+    }
+
+    /// Tries up to `attempts` more nonces, calling `on_progress` every
+    /// `MINING_PROGRESS_INTERVAL` of them.
+    pub fn mine<F>(
+        &mut self,
+        attempts: u64,
+        mut on_progress: F,
+    ) -> Result<MiningBlockHeader, MiningError>
+    where
+        F: FnMut(&MiningProgress),
+    {
+        let mut remaining = attempts;
+        while remaining > 0 {
+            if self.cancellation.is_cancelled() {
+                return Err(MiningError::Cancelled);
+            }
+            if self.next_nonce == u64::MAX {
+                self.roll_created_at();
+            }
+            let batch = remaining
+                .min(MINING_PROGRESS_INTERVAL)
+                .min(u64::MAX - self.next_nonce);
+            let start = Instant::now();
+            let (header_mined, stats) = search_nonce(
+                &self.header,
+                self.next_nonce..self.next_nonce + batch,
+                self.nb_threads as u64,
+                &self.cancellation.0,
+            );
+            self.elapsed += start.elapsed();
+            self.attempts += stats.hashes();
+            remaining -= batch;
+            if let Some(header_mined) = header_mined {
+                self.next_nonce = header_mined.nonce + 1;
+                return Ok(header_mined);
+            }
+            // Workers may stop unevenly when cancelled: resume from the
+            // first nonce one of them did not try.
+            let min_hashes = stats.hashes_per_worker.iter().min().copied().unwrap_or(0);
+            self.next_nonce += batch.min(min_hashes * self.nb_threads as u64);
+            on_progress(&self.get_progress());
+        }
+        if self.cancellation.is_cancelled() {
+            return Err(MiningError::Cancelled);
+        }
+        Err(MiningError::UnsuccessfulMining)
+    }
+
+    fn roll_created_at(&mut self) {
+        self.header.created_at = Utc::now().timestamp().max(self.header.created_at + 1);
+        self.next_nonce = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bigdecimal::BigDecimal;
+
+    use super::*;
+    use crate::address::Address;
+    use crate::pow::BASE_MINING_BITS;
+
+    // Target of 1, no hash meets it
+    const NO_HASH_BITS: u32 = 0x03000001;
+
+    fn get_test_header(bits: u32) -> MiningBlockHeader {
+        MiningBlockHeader::new(
+            &sha256::digest("root"),
+            &sha256::digest("prev"),
+            1,
+            1,
+            bits,
+            BigDecimal::from(1),
+            Address::named("miner.eth"),
+        )
+    }
+
+    #[test]
+    fn test_session_resumes_from_last_nonce() {
+        let mut session = MiningSession::new(get_test_header(NO_HASH_BITS), 2);
+        let mut reports = 0;
+        let result = session.mine(MINING_PROGRESS_INTERVAL * 2, |_| reports += 1);
+        assert_eq!(result.unwrap_err(), MiningError::UnsuccessfulMining);
+        assert_eq!(reports, 2);
+        assert_eq!(session.get_progress().nonce, MINING_PROGRESS_INTERVAL * 2);
+
+        let _ = session.mine(10, |_| {});
+        let progress = session.get_progress();
+        assert_eq!(progress.nonce, MINING_PROGRESS_INTERVAL * 2 + 10);
+        assert_eq!(progress.attempts, MINING_PROGRESS_INTERVAL * 2 + 10);
+    }
+
+    #[test]
+    fn test_session_finds_header() {
+        let mut session = MiningSession::new(get_test_header(BASE_MINING_BITS), 4);
+        let header = session.mine(u64::MAX, |_| {}).unwrap();
+        assert!(header.is_pow_computation_valid());
+    }
+
+    #[test]
+    fn test_cancelled_session_stops() {
+        let mut session = MiningSession::new(get_test_header(NO_HASH_BITS), 2);
+        let token = session.cancellation_token();
+        let result = session.mine(u64::MAX, |progress| {
+            if progress.attempts >= MINING_PROGRESS_INTERVAL {
+                token.cancel();
+            }
+        });
+        assert_eq!(result.unwrap_err(), MiningError::Cancelled);
+        assert_eq!(session.get_progress().attempts, MINING_PROGRESS_INTERVAL);
+    }
+
+    #[test]
+    fn test_nonce_exhaustion_rolls_created_at() {
+        let mut header = get_test_header(NO_HASH_BITS);
+        header.nonce = u64::MAX - 5;
+        let created_at = header.created_at;
+        let mut session = MiningSession::new(header, 1);
+        let _ = session.mine(10, |_| {});
+        assert!(session.header().created_at > created_at);
+        assert_eq!(session.get_progress().nonce, 5);
+    }
+}
@@ -0,0 +1,105 @@
+use std::io::{self, Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use super::sync::BlockBody;
+use crate::block::block_header::MiningBlockHeader;
+use crate::block::Block;
+use crate::hashable::Hashable;
+use crate::transaction::Transaction;
+
+/// Largest message accepted from a peer, in bytes.
+pub const MAX_MESSAGE_SIZE: u32 = 32 * 1024 * 1024;
+
+/// Messages exchanged between nodes.
+///
+/// On the wire every message is `[payload length: u32 LE][bincode payload]`.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Message {
+    /// First message sent on every connection, peers on another chain are
+    /// disconnected.
+    Hello {
+        height: u64,
+        tip_hash: String,
+        genesis_hash: String,
+        chain_id: u64,
+    },
+    NewBlock(Box<Block>),
+    NewTx(Box<Transaction>),
+    GetBlock {
+        block_hash: String,
+    },
+    Blocks(Vec<Block>),
+    /// Mined headers of the active chain following the first locator block
+    /// known by the peer, see `sync::get_locator`.
+    GetHeaders {
+        locator: Vec<String>,
+    },
+    Headers(Vec<MiningBlockHeader>),
+    GetBodies {
+        block_hashes: Vec<String>,
+    },
+    Bodies(Vec<BlockBody>),
+}
+
+impl Hashable for Message {}
+
+pub fn write_message<W: Write>(writer: &mut W, message: &Message) -> io::Result<()> {
+    let payload = message.to_bytes();
+    writer.write_all(&(payload.len() as u32).to_le_bytes())?;
+    writer.write_all(&payload)?;
+    writer.flush()
+}
+
+pub fn read_message<R: Read>(reader: &mut R) -> io::Result<Message> {
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len);
+    if len > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message too large",
+        ));
+    }
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload)?;
+    bincode::deserialize(&payload).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::Address;
+    use crate::wallet::Wallet;
+
+    #[test]
+    fn test_message_roundtrip() {
+        let mut wallet = Wallet::new();
+        let genesis =
+            Block::genesis(&[Transaction::allocation(wallet.address(), 100_000.into(), 0)])
+                .unwrap();
+        let mut buffer = Vec::new();
+        write_message(&mut buffer, &Message::Blocks(vec![genesis.clone()])).unwrap();
+        write_message(
+            &mut buffer,
+            &Message::NewTx(Box::new(wallet.send(Address::named("adel.eth"), 1.into()))),
+        )
+        .unwrap();
+
+        let mut reader = buffer.as_slice();
+        match read_message(&mut reader).unwrap() {
+            Message::Blocks(blocks) => {
+                assert_eq!(blocks.len(), 1);
+                assert!(blocks[0].verify().is_ok());
+                assert_eq!(blocks[0].block_hash, genesis.block_hash);
+            }
+            _ => panic!("unexpected message"),
+        }
+        assert!(matches!(read_message(&mut reader), Ok(Message::NewTx(_))));
+        assert!(read_message(&mut reader).is_err());
+
+        let too_large = (MAX_MESSAGE_SIZE + 1).to_le_bytes();
+        let err = read_message(&mut too_large.as_slice()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
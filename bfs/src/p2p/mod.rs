@@ -0,0 +1,592 @@
+pub mod message;
+pub mod sync;
+
+pub use message::Message;
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::block::block_header::MiningBlockHeader;
+use crate::block::{Block, BlockError};
+use crate::blockchain::Blockchain;
+use crate::mempool::Mempool;
+use crate::miner::MiningError;
+use crate::transaction::Transaction;
+use message::{read_message, write_message};
+use sync::{get_headers_after, get_locator, BlockBody, HeaderSync, HEADERS_BATCH_SIZE};
+
+pub const P2P_DEFAULT_ADDRESS: &str = "127.0.0.1:6000";
+/// A peer not reading its messages for that long is dropped.
+pub const P2P_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Most orphan blocks kept, the oldest are evicted first.
+pub const MAX_ORPHANS: usize = 100;
+/// Most orphan blocks kept from a single peer.
+pub const MAX_ORPHANS_PER_PEER: usize = 20;
+/// Orphans whose parent didn't show up for that long are dropped.
+pub const ORPHAN_EXPIRY: Duration = Duration::from_secs(10 * 60);
+
+/// A bfs node sharing its blockchain and mempool with its peers over TCP.
+///
+/// New blocks & transactions are gossiped: once accepted they are relayed to
+/// every other peer, and ignored when already known so announces die out.
+/// Blocks whose parent is unknown are kept until the parent is fetched, see
+/// `Orphans` for the limits.
+///
+/// A peer whose tip is unknown is synced with headers-first (see `HeaderSync`).
+#[derive(Clone)]
+pub struct Node {
+    listener: Arc<TcpListener>,
+    blockchain: Arc<RwLock<Blockchain>>,
+    mempool: Arc<RwLock<Mempool>>,
+    peers: Arc<Mutex<HashMap<SocketAddr, TcpStream>>>,
+    orphans: Arc<Mutex<Orphans>>,
+    sync: Arc<Mutex<HeaderSync>>,
+}
+
+impl Node {
+    pub fn bind<A: ToSocketAddrs>(
+        address: A,
+        blockchain: Arc<RwLock<Blockchain>>,
+        mempool: Arc<RwLock<Mempool>>,
+    ) -> io::Result<Node> {
+        Ok(Node {
+            listener: Arc::new(TcpListener::bind(address)?),
+            blockchain,
+            mempool,
+            peers: Arc::default(),
+            orphans: Arc::default(),
+            sync: Arc::default(),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.listener.local_addr().unwrap()
+    }
+
+    /// Accepts incoming peers from a background thread.
+    pub fn spawn(&self) -> JoinHandle<()> {
+        let node = self.clone();
+        thread::spawn(move || {
+            for stream in node.listener.incoming().flatten() {
+                let _ = node.add_peer(stream);
+            }
+        })
+    }
+
+    /// Connects to a peer, which starts syncing with it if it is ahead.
+    pub fn connect<A: ToSocketAddrs>(&self, address: A) -> io::Result<()> {
+        self.add_peer(TcpStream::connect(address)?)
+    }
+
+    pub fn peers_count(&self) -> usize {
+        self.peers.lock().unwrap().len()
+    }
+
+    /// Announces a block accepted locally, e.g. just mined.
+    pub fn announce_block(&self, block: &Block) {
+        self.broadcast(&Message::NewBlock(Box::new(block.clone())), None);
+    }
+
+    /// Announces a transaction accepted in the local mempool.
+    pub fn announce_tx(&self, tx: &Transaction) {
+        self.broadcast(&Message::NewTx(Box::new(tx.clone())), None);
+    }
+
+    fn add_peer(&self, stream: TcpStream) -> io::Result<()> {
+        let peer = stream.peer_addr()?;
+        stream.set_write_timeout(Some(P2P_WRITE_TIMEOUT))?;
+        let mut reader = stream.try_clone()?;
+        self.peers.lock().unwrap().insert(peer, stream);
+        self.send(peer, &self.get_hello());
+        let node = self.clone();
+        thread::spawn(move || {
+            while let Ok(message) = read_message(&mut reader) {
+                node.handle(peer, message);
+            }
+            node.peers.lock().unwrap().remove(&peer);
+            node.sync.lock().unwrap().remove_peer(peer);
+            node.request_bodies();
+        });
+        Ok(())
+    }
+
+    fn get_hello(&self) -> Message {
+        let blockchain = self.blockchain.read().unwrap();
+        let tip = blockchain.get_last_block();
+        Message::Hello {
+            height: tip.block_header.block_number,
+            tip_hash: tip.block_hash.clone(),
+            genesis_hash: blockchain.blocks[0].block_hash.clone(),
+            chain_id: blockchain.params.chain_id,
+        }
+    }
+
+    fn handle(&self, peer: SocketAddr, message: Message) {
+        match message {
+            Message::Hello {
+                tip_hash,
+                genesis_hash,
+                chain_id,
+                ..
+            } => {
+                let blockchain = self.blockchain.read().unwrap();
+                if genesis_hash != blockchain.blocks[0].block_hash
+                    || chain_id != blockchain.params.chain_id
+                {
+                    drop(blockchain);
+                    self.disconnect(peer);
+                } else if !blockchain.is_known_block(&tip_hash) {
+                    let locator = get_locator(&blockchain);
+                    drop(blockchain);
+                    self.send(peer, &Message::GetHeaders { locator });
+                }
+            }
+            Message::NewBlock(block) => self.receive_block(peer, *block, true),
+            Message::NewTx(tx) => {
+                let accepted = {
+                    let blockchain = self.blockchain.read().unwrap();
+                    let mut mempool = self.mempool.write().unwrap();
+                    mempool.add((*tx).clone(), &blockchain.state).is_ok()
+                };
+                if accepted {
+                    self.broadcast(&Message::NewTx(tx), Some(peer));
+                }
+            }
+            Message::GetBlock { block_hash } => {
+                let blocks = {
+                    let blockchain = self.blockchain.read().unwrap();
+                    blockchain.get_known_block(&block_hash).cloned()
+                };
+                self.send(peer, &Message::Blocks(blocks.into_iter().collect()));
+            }
+            Message::Blocks(blocks) => {
+                for block in blocks {
+                    self.receive_block(peer, block, false);
+                }
+            }
+            Message::GetHeaders { locator } => {
+                let headers = {
+                    let blockchain = self.blockchain.read().unwrap();
+                    get_headers_after(&blockchain, &locator, HEADERS_BATCH_SIZE)
+                };
+                self.send(peer, &Message::Headers(headers));
+            }
+            Message::Headers(headers) => self.receive_headers(peer, headers),
+            Message::GetBodies { block_hashes } => {
+                let bodies = {
+                    let blockchain = self.blockchain.read().unwrap();
+                    block_hashes
+                        .into_iter()
+                        .filter_map(|block_hash| {
+                            let block = blockchain.get_known_block(&block_hash)?;
+                            Some(BlockBody {
+                                txs: block.txs.values().cloned().collect(),
+                                block_hash,
+                            })
+                        })
+                        .collect()
+                };
+                self.send(peer, &Message::Bodies(bodies));
+            }
+            Message::Bodies(bodies) => self.receive_bodies(bodies),
+        }
+    }
+
+    /// Validates a batch of headers, asking for the next one until the peer
+    /// has sent all its headers (or as many as can be kept), whose bodies are
+    /// then fetched if it is the heaviest chain.
+    fn receive_headers(&self, peer: SocketAddr, headers: Vec<MiningBlockHeader>) {
+        let is_full_batch = headers.len() as u64 == HEADERS_BATCH_SIZE;
+        let last_hash = headers.last().map(|header| header.get_block_hash());
+        let wants_more = {
+            let blockchain = self.blockchain.read().unwrap();
+            let mut sync = self.sync.lock().unwrap();
+            let Ok(has_room) = sync.add_headers(&blockchain, peer, headers) else {
+                return;
+            };
+            let wants_more = is_full_batch && has_room;
+            if !wants_more {
+                sync.select_target(&blockchain, peer);
+            }
+            wants_more
+        };
+        match last_hash {
+            Some(last_hash) if wants_more => {
+                let locator = vec![last_hash];
+                self.send(peer, &Message::GetHeaders { locator });
+            }
+            _ => self.request_bodies(),
+        }
+    }
+
+    /// Connects the blocks whose bodies have been received in chain order.
+    ///
+    /// Once the target chain is connected, peers are asked for the headers
+    /// following it in case they had more than could be kept.
+    fn receive_bodies(&self, bodies: Vec<BlockBody>) {
+        let (ready, is_done) = {
+            let mut sync = self.sync.lock().unwrap();
+            for body in bodies {
+                let _ = sync.add_body(body);
+            }
+            let ready = sync.take_ready_blocks();
+            let is_done = !ready.is_empty() && !sync.is_syncing();
+            (ready, is_done)
+        };
+        for block in ready {
+            let mut blockchain = self.blockchain.write().unwrap();
+            match blockchain.add_block(&block) {
+                Ok(()) | Err(BlockError::DuplicatedBlock) => {
+                    let mut mempool = self.mempool.write().unwrap();
+                    mempool.reinject(blockchain.take_disconnected_txs(), &blockchain.state);
+                    mempool.remove_block_txs(&block, &blockchain.state);
+                }
+                Err(_) => {
+                    self.sync.lock().unwrap().reset();
+                    return;
+                }
+            }
+        }
+        if is_done {
+            let locator = get_locator(&self.blockchain.read().unwrap());
+            self.broadcast(&Message::GetHeaders { locator }, None);
+        }
+        self.request_bodies();
+    }
+
+    fn request_bodies(&self) {
+        let requests = self.sync.lock().unwrap().next_requests();
+        for (peer, block_hashes) in requests {
+            self.send(peer, &Message::GetBodies { block_hashes });
+        }
+    }
+
+    /// Adds a block received from a peer, relaying it if asked to.
+    fn receive_block(&self, peer: SocketAddr, block: Block, relay: bool) {
+        let result = {
+            let mut blockchain = self.blockchain.write().unwrap();
+            if blockchain.is_known_block(&block.block_hash) {
+                return;
+            }
+            let result = blockchain.add_block(&block);
+            if result.is_ok() {
+                let mut mempool = self.mempool.write().unwrap();
+                mempool.reinject(blockchain.take_disconnected_txs(), &blockchain.state);
+                mempool.remove_block_txs(&block, &blockchain.state);
+            }
+            result
+        };
+        match result {
+            Ok(()) => {
+                if relay {
+                    self.broadcast(&Message::NewBlock(Box::new(block.clone())), Some(peer));
+                }
+                let children = self
+                    .orphans
+                    .lock()
+                    .unwrap()
+                    .take_children(&block.block_hash);
+                for child in children {
+                    self.receive_block(peer, child, false);
+                }
+            }
+            Err(BlockError::InvalidMinedHeader(MiningError::InvalidPrevHash)) => {
+                let block_hash = block.block_header.prev_hash.clone();
+                self.orphans.lock().unwrap().add(peer, block);
+                self.send(peer, &Message::GetBlock { block_hash });
+            }
+            Err(_) => {}
+        }
+    }
+
+    fn send(&self, peer: SocketAddr, message: &Message) {
+        let mut peers = self.peers.lock().unwrap();
+        if let Some(stream) = peers.get_mut(&peer) {
+            if write_message(stream, message).is_err() {
+                peers.remove(&peer);
+            }
+        }
+    }
+
+    fn disconnect(&self, peer: SocketAddr) {
+        // Shutting down also stops the thread reading from the peer
+        if let Some(stream) = self.peers.lock().unwrap().remove(&peer) {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    fn broadcast(&self, message: &Message, except: Option<SocketAddr>) {
+        let mut peers = self.peers.lock().unwrap();
+        peers
+            .retain(|peer, stream| Some(*peer) == except || write_message(stream, message).is_ok());
+    }
+}
+
+struct Orphan {
+    peer: SocketAddr,
+    received_at: Instant,
+    block: Block,
+}
+
+/// Blocks waiting for their parent, oldest first.
+///
+/// At most `MAX_ORPHANS_PER_PEER` are kept per peer and `MAX_ORPHANS` in
+/// total, so a peer sending blocks on an unknown chain can't exhaust memory.
+#[derive(Default)]
+struct Orphans(Vec<Orphan>);
+
+impl Orphans {
+    fn add(&mut self, peer: SocketAddr, block: Block) {
+        if self
+            .0
+            .iter()
+            .any(|orphan| orphan.block.block_hash == block.block_hash)
+        {
+            return;
+        }
+        self.0
+            .retain(|orphan| orphan.received_at.elapsed() < ORPHAN_EXPIRY);
+        let from_peer = self.0.iter().filter(|orphan| orphan.peer == peer).count();
+        if from_peer >= MAX_ORPHANS_PER_PEER {
+            let oldest = self
+                .0
+                .iter()
+                .position(|orphan| orphan.peer == peer)
+                .unwrap();
+            self.0.remove(oldest);
+        } else if self.0.len() >= MAX_ORPHANS {
+            self.0.remove(0);
+        }
+        self.0.push(Orphan {
+            peer,
+            received_at: Instant::now(),
+            block,
+        });
+    }
+
+    /// Removes the blocks whose parent is `block_hash`.
+    fn take_children(&mut self, block_hash: &str) -> Vec<Block> {
+        let (children, others) = self
+            .0
+            .drain(..)
+            .partition(|orphan| orphan.block.block_header.prev_hash == block_hash);
+        self.0 = others;
+        children
+            .into_iter()
+            .map(|orphan: Orphan| orphan.block)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::address::Address;
+    use crate::hashable::Hashable;
+    use crate::miner::Miner;
+    use crate::wallet::Wallet;
+
+    fn get_test_genesis(wallet: &Wallet) -> Block {
+        let allocation = Transaction::allocation(wallet.address(), 100_000.into(), 0);
+        Block::genesis(&[allocation]).unwrap()
+    }
+
+    fn get_test_node(genesis: &Block) -> Node {
+        let blockchain = Blockchain::from_genesis_block(genesis.clone()).unwrap();
+        let node = Node::bind(
+            "127.0.0.1:0",
+            Arc::new(RwLock::new(blockchain)),
+            Arc::default(),
+        )
+        .unwrap();
+        node.spawn();
+        node
+    }
+
+    fn mine_next(node: &Node, miner: &mut Miner) -> Block {
+        let mut blockchain = node.blockchain.write().unwrap();
+        let txs = vec![miner.build_coinbase(&blockchain)];
+        let header = miner
+            .mine_next_block(&blockchain, &txs, Some(u64::MAX))
+            .unwrap();
+        let block = blockchain.build_block_candidate(&header, &txs).unwrap();
+        blockchain.add_block(&block).unwrap();
+        block
+    }
+
+    impl Node {
+        fn get_height(&self) -> u64 {
+            let blockchain = self.blockchain.read().unwrap();
+            blockchain.get_last_block().block_header.block_number
+        }
+
+        fn get_hello_tip_hash(&self) -> String {
+            match self.get_hello() {
+                Message::Hello { tip_hash, .. } => tip_hash,
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    fn wait_until<F: Fn() -> bool>(condition: F) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(10) {
+            if condition() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    #[test]
+    fn test_initial_block_download() {
+        let wallet = Wallet::new();
+        let genesis = get_test_genesis(&wallet);
+        let node_a = get_test_node(&genesis);
+        let node_b = get_test_node(&genesis);
+        let mut miner = Miner::new(wallet);
+        for _ in 0..3 {
+            mine_next(&node_a, &mut miner);
+        }
+        let tip_hash = node_a.get_hello_tip_hash();
+
+        node_b.connect(node_a.local_addr()).unwrap();
+        assert!(wait_until(|| node_b.get_height() == 3));
+        let blockchain = node_b.blockchain.read().unwrap();
+        assert_eq!(blockchain.get_last_block().block_hash, tip_hash);
+    }
+
+    #[test]
+    fn test_sync_switches_to_heaviest_fork() {
+        let wallet = Wallet::new();
+        let genesis = get_test_genesis(&wallet);
+        let node_a = get_test_node(&genesis);
+        let node_b = get_test_node(&genesis);
+        let node_c = get_test_node(&genesis);
+        let mut miner_a = Miner::new(wallet);
+        let mut miner_b = Miner::new(Wallet::new());
+        for _ in 0..4 {
+            let block = mine_next(&node_a, &mut miner_a);
+            node_c
+                .blockchain
+                .write()
+                .unwrap()
+                .add_block(&block)
+                .unwrap();
+        }
+        mine_next(&node_b, &mut miner_b);
+        let tip_hash = node_a.get_hello_tip_hash();
+
+        // Bodies are downloaded from both a and c
+        node_b.connect(node_a.local_addr()).unwrap();
+        node_b.connect(node_c.local_addr()).unwrap();
+        assert!(wait_until(|| node_b.get_hello_tip_hash() == tip_hash));
+        assert!(wait_until(|| !node_b.sync.lock().unwrap().is_syncing()));
+        // a doesn't switch to the lighter chain of b
+        assert_eq!(node_a.get_hello_tip_hash(), tip_hash);
+    }
+
+    #[test]
+    fn test_blocks_and_txs_are_gossiped() {
+        let mut wallet = Wallet::new();
+        let genesis = get_test_genesis(&wallet);
+        let node_a = get_test_node(&genesis);
+        let node_b = get_test_node(&genesis);
+        let node_c = get_test_node(&genesis);
+        // a <-> b <-> c: c only hears about a through b
+        node_b.connect(node_a.local_addr()).unwrap();
+        node_c.connect(node_b.local_addr()).unwrap();
+        assert!(wait_until(
+            || node_a.peers_count() == 1 && node_b.peers_count() == 2
+        ));
+
+        let mut miner = Miner::new(Wallet::new());
+        let block = mine_next(&node_a, &mut miner);
+        node_a.announce_block(&block);
+        assert!(wait_until(|| node_c.get_height() == 1));
+
+        let tx = wallet.send(Address::named("adel.eth"), 1.into());
+        {
+            let blockchain = node_a.blockchain.read().unwrap();
+            let mut mempool = node_a.mempool.write().unwrap();
+            mempool.add(tx.clone(), &blockchain.state).unwrap();
+        }
+        node_a.announce_tx(&tx);
+        assert!(wait_until(|| node_c
+            .mempool
+            .read()
+            .unwrap()
+            .get(&tx.get_hash())
+            .is_some()));
+    }
+
+    #[test]
+    fn test_orphan_block_fetches_its_parent() {
+        let wallet = Wallet::new();
+        let genesis = get_test_genesis(&wallet);
+        let node_a = get_test_node(&genesis);
+        let node_b = get_test_node(&genesis);
+        node_b.connect(node_a.local_addr()).unwrap();
+        assert!(wait_until(|| node_a.peers_count() == 1));
+
+        // b misses the announce of the first block
+        let mut miner = Miner::new(Wallet::new());
+        mine_next(&node_a, &mut miner);
+        let block = mine_next(&node_a, &mut miner);
+        node_a.announce_block(&block);
+        assert!(wait_until(|| node_b.get_height() == 2));
+        assert!(node_b.orphans.lock().unwrap().0.is_empty());
+    }
+
+    #[test]
+    fn test_peer_on_another_chain_is_disconnected() {
+        let node_a = get_test_node(&get_test_genesis(&Wallet::new()));
+        let node_b = get_test_node(&get_test_genesis(&Wallet::new()));
+        node_b.connect(node_a.local_addr()).unwrap();
+        assert!(wait_until(
+            || node_a.peers_count() == 0 && node_b.peers_count() == 0
+        ));
+    }
+
+    #[test]
+    fn test_orphans_are_bounded() {
+        let genesis = get_test_genesis(&Wallet::new());
+        let get_orphan = |i: usize| {
+            let mut block = genesis.clone();
+            block.block_hash = format!("orphan {}", i);
+            block.block_header.prev_hash = format!("parent {}", i);
+            block
+        };
+        let peer_a: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let peer_b: SocketAddr = "127.0.0.1:2".parse().unwrap();
+        let mut orphans = Orphans::default();
+
+        // A peer only evicts its own oldest orphans
+        orphans.add(peer_b, get_orphan(0));
+        for i in 1..=MAX_ORPHANS_PER_PEER + 1 {
+            orphans.add(peer_a, get_orphan(i));
+        }
+        assert_eq!(orphans.0.len(), MAX_ORPHANS_PER_PEER + 1);
+        assert!(orphans.take_children("parent 1").is_empty());
+        assert_eq!(orphans.take_children("parent 0").len(), 1);
+        assert_eq!(orphans.take_children("parent 2").len(), 1);
+        // Already known orphans are ignored
+        orphans.add(peer_a, get_orphan(3));
+        assert_eq!(orphans.0.len(), MAX_ORPHANS_PER_PEER - 1);
+
+        for i in 0..MAX_ORPHANS + 1 {
+            let peer = SocketAddr::from(([127, 0, 0, 1], 10 + i as u16));
+            orphans.add(peer, get_orphan(1000 + i));
+        }
+        assert_eq!(orphans.0.len(), MAX_ORPHANS);
+        assert!(orphans.take_children("parent 3").is_empty());
+        assert_eq!(orphans.take_children("parent 1100").len(), 1);
+    }
+}
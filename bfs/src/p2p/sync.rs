@@ -0,0 +1,485 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+
+use num_bigint::BigUint;
+use serde::{Deserialize, Serialize};
+
+use crate::block::block_header::MiningBlockHeader;
+use crate::block::{Block, BlockError, BlockHeader};
+use crate::blockchain::Blockchain;
+use crate::miner::MiningError;
+use crate::transaction::Transaction;
+
+/// Number of headers sent at once.
+pub const HEADERS_BATCH_SIZE: u64 = 500;
+/// Number of block bodies requested at once from a peer.
+pub const BODIES_BATCH_SIZE: usize = 16;
+/// Number of block bodies that can be downloaded ahead of the active chain.
+pub const SYNC_WINDOW: usize = 256;
+/// Most headers kept from a peer before downloading their bodies.
+pub const MAX_PEER_HEADERS: usize = 20_000;
+
+/// Transactions of a block, checked against the Merkle root of its header.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BlockBody {
+    pub block_hash: String,
+    pub txs: Vec<Transaction>,
+}
+
+/// Hashes of active chain blocks, from the tip back to the genesis block with
+/// an exponentially growing step, for a peer to find where our chains fork.
+pub fn get_locator(blockchain: &Blockchain) -> Vec<String> {
+    let mut locator = Vec::new();
+    let mut block_number = blockchain.get_last_block().block_header.block_number;
+    let mut step = 1;
+    loop {
+        locator.push(
+            blockchain
+                .get_block(block_number)
+                .unwrap()
+                .block_hash
+                .clone(),
+        );
+        if block_number == 0 {
+            return locator;
+        }
+        if locator.len() >= 10 {
+            step *= 2;
+        }
+        block_number = block_number.saturating_sub(step);
+    }
+}
+
+/// Mined headers of the active chain following the first locator block found.
+pub fn get_headers_after(
+    blockchain: &Blockchain,
+    locator: &[String],
+    count: u64,
+) -> Vec<MiningBlockHeader> {
+    let Some(fork) = locator
+        .iter()
+        .find_map(|block_hash| blockchain.get_block_by_hash(block_hash))
+    else {
+        return Vec::new();
+    };
+    let from = fork.block_header.block_number + 1;
+    (from..from.saturating_add(count.min(HEADERS_BATCH_SIZE)))
+        .map_while(|block_number| blockchain.get_block(block_number))
+        .map(|block| MiningBlockHeader::from(&block.block_header))
+        .collect()
+}
+
+/// Checks that the headers of `chain` from `from` on are chained one after the
+/// other, `chain` starting right after a known block, and carry a valid proof
+/// of work at the target the retarget rules expect.
+pub fn validate_headers(
+    blockchain: &Blockchain,
+    chain: &[MiningBlockHeader],
+    from: usize,
+) -> Result<(), MiningError> {
+    let Some(first) = chain.first() else {
+        return Ok(());
+    };
+    let fork = blockchain
+        .get_known_block(&first.prev_hash)
+        .ok_or(MiningError::InvalidPrevHash)?;
+    let (mut prev_hash, mut prev_number, mut prev_created_at) = match from.checked_sub(1) {
+        Some(i) => (
+            chain[i].get_block_hash(),
+            chain[i].block_number,
+            chain[i].created_at,
+        ),
+        None => (
+            fork.block_hash.clone(),
+            fork.block_header.block_number,
+            fork.block_header.created_at,
+        ),
+    };
+    for (i, header) in chain.iter().enumerate().skip(from) {
+        if !header.is_pow_computation_valid() {
+            return Err(MiningError::InvalidProofOfWork);
+        }
+        if header.prev_hash != prev_hash {
+            return Err(MiningError::InvalidPrevHash);
+        }
+        if header.block_number != prev_number + 1 {
+            return Err(MiningError::InvalidBlockNumber {
+                expected: prev_number + 1,
+                got: header.block_number,
+            });
+        }
+        let expected_bits = blockchain
+            .get_next_bits_after(&fork.block_hash, &chain[..i])
+            .unwrap();
+        if header.bits != expected_bits {
+            return Err(MiningError::InvalidDifficulty {
+                expected: expected_bits,
+                got: header.bits,
+            });
+        }
+        if header.created_at < prev_created_at {
+            return Err(MiningError::InvalidTimestamp);
+        }
+        prev_hash = header.get_block_hash();
+        prev_number = header.block_number;
+        prev_created_at = header.created_at;
+    }
+    Ok(())
+}
+
+/// Headers-first synchronization: peers first send their header chains, which
+/// are checked for linkage & proof of work. The bodies of the heaviest chain
+/// are then downloaded from every peer having it, and checked against the
+/// Merkle roots of the headers.
+///
+/// At most `max_peer_headers` are kept per peer: a longer chain is synced in
+/// several rounds, its remaining headers being asked for once the first ones
+/// are connected.
+pub struct HeaderSync {
+    /// Validated headers received from each peer, following a known block.
+    peer_headers: HashMap<SocketAddr, Vec<MiningBlockHeader>>,
+    max_peer_headers: usize,
+    /// Headers of the best chain whose blocks are not connected yet.
+    target: VecDeque<MiningBlockHeader>,
+    target_work: BigUint,
+    /// Peers the best chain can be downloaded from.
+    sources: Vec<SocketAddr>,
+    requested: HashSet<String>,
+    bodies: HashMap<String, Block>,
+}
+
+impl Default for HeaderSync {
+    fn default() -> Self {
+        HeaderSync {
+            peer_headers: HashMap::new(),
+            max_peer_headers: MAX_PEER_HEADERS,
+            target: VecDeque::new(),
+            target_work: BigUint::ZERO,
+            sources: Vec::new(),
+            requested: HashSet::new(),
+            bodies: HashMap::new(),
+        }
+    }
+}
+
+impl HeaderSync {
+    pub fn new() -> HeaderSync {
+        HeaderSync::default()
+    }
+
+    pub fn is_syncing(&self) -> bool {
+        !self.target.is_empty()
+    }
+
+    /// Validates headers sent by a peer, either continuing its previous batch
+    /// or starting from a block we know. Returns whether more headers can be
+    /// taken from the peer, the ones over the limit being ignored.
+    pub fn add_headers(
+        &mut self,
+        blockchain: &Blockchain,
+        peer: SocketAddr,
+        headers: Vec<MiningBlockHeader>,
+    ) -> Result<bool, MiningError> {
+        let chain = self.peer_headers.entry(peer).or_default();
+        let Some(first) = headers.first() else {
+            return Ok(chain.len() < self.max_peer_headers);
+        };
+        if chain
+            .last()
+            .is_none_or(|last| last.get_block_hash() != first.prev_hash)
+        {
+            chain.clear();
+        }
+        let from = chain.len();
+        chain.extend(headers.into_iter().take(self.max_peer_headers - from));
+        match validate_headers(blockchain, chain, from) {
+            Ok(()) => Ok(chain.len() < self.max_peer_headers),
+            Err(err) => {
+                self.peer_headers.remove(&peer);
+                Err(err)
+            }
+        }
+    }
+
+    /// Once a peer has sent all its headers, makes its chain the one to
+    /// download if it carries more work than the active one and the current
+    /// target. Returns whether the peer can be downloaded from.
+    pub fn select_target(&mut self, blockchain: &Blockchain, peer: SocketAddr) -> bool {
+        let Some(headers) = self.peer_headers.remove(&peer) else {
+            return false;
+        };
+        let Some(first) = headers.first() else {
+            return false;
+        };
+        let Some(fork_work) = blockchain.get_chain_work(&first.prev_hash) else {
+            return false;
+        };
+        let work = fork_work + headers.iter().map(|h| h.get_work()).sum::<BigUint>();
+        let last_hash = headers.last().map(|header| header.get_block_hash());
+        let target_hash = self.target.back().map(|header| header.get_block_hash());
+        if self.is_syncing() && last_hash == target_hash {
+            self.sources.push(peer);
+            return true;
+        }
+        if work <= blockchain.get_total_work() || (self.is_syncing() && work <= self.target_work) {
+            return false;
+        }
+        self.target = headers.into();
+        self.target_work = work;
+        self.sources = vec![peer];
+        self.requested.clear();
+        self.bodies.clear();
+        true
+    }
+
+    /// Splits the next missing bodies of the target chain in batches, each
+    /// one to be requested from a different source peer.
+    pub fn next_requests(&mut self) -> Vec<(SocketAddr, Vec<String>)> {
+        if self.sources.is_empty() {
+            return Vec::new();
+        }
+        let missing: Vec<String> = self
+            .target
+            .iter()
+            .take(SYNC_WINDOW)
+            .map(|header| header.get_block_hash())
+            .filter(|block_hash| {
+                !self.bodies.contains_key(block_hash) && !self.requested.contains(block_hash)
+            })
+            .collect();
+        self.requested.extend(missing.iter().cloned());
+        missing
+            .chunks(BODIES_BATCH_SIZE)
+            .enumerate()
+            .map(|(i, chunk)| (self.sources[i % self.sources.len()], chunk.to_vec()))
+            .collect()
+    }
+
+    /// Rebuilds the block of a requested body, which must match its header.
+    pub fn add_body(&mut self, body: BlockBody) -> Result<(), BlockError> {
+        if !self.requested.remove(&body.block_hash) {
+            return Err(BlockError::InvalidBlockHash);
+        }
+        let header = self
+            .target
+            .iter()
+            .find(|header| header.get_block_hash() == body.block_hash)
+            .ok_or(BlockError::InvalidBlockHash)?;
+        let block = Block::new(BlockHeader::from(header), &body.txs)?;
+        self.bodies.insert(body.block_hash, block);
+        Ok(())
+    }
+
+    /// Takes the blocks that can be connected next, in chain order.
+    pub fn take_ready_blocks(&mut self) -> Vec<Block> {
+        let mut ready = Vec::new();
+        while let Some(header) = self.target.front() {
+            let Some(block) = self.bodies.remove(&header.get_block_hash()) else {
+                break;
+            };
+            self.target.pop_front();
+            ready.push(block);
+        }
+        if self.target.is_empty() {
+            self.reset();
+        }
+        ready
+    }
+
+    /// Gives up on the current target, e.g. when one of its blocks is invalid.
+    pub fn reset(&mut self) {
+        self.target.clear();
+        self.target_work = BigUint::ZERO;
+        self.sources.clear();
+        self.requested.clear();
+        self.bodies.clear();
+    }
+
+    /// Forgets a disconnected peer.
+    pub fn remove_peer(&mut self, peer: SocketAddr) {
+        self.peer_headers.remove(&peer);
+        self.sources.retain(|source| *source != peer);
+        // Bodies requested from it are requested again from other sources.
+        self.requested.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::ConsensusParams;
+    use crate::miner::Miner;
+    use crate::pow::POW_LIMIT_BITS;
+    use crate::wallet::Wallet;
+
+    // Easiest target and no retargeting, to mine long chains quickly
+    fn get_test_params() -> ConsensusParams {
+        ConsensusParams {
+            initial_bits: POW_LIMIT_BITS,
+            difficulty_adjustment_interval: 0,
+            ..ConsensusParams::default()
+        }
+    }
+
+    fn get_test_chain(nb_blocks: usize) -> Blockchain {
+        get_test_chain_with(nb_blocks, get_test_params())
+    }
+
+    fn get_test_chain_with(nb_blocks: usize, params: ConsensusParams) -> Blockchain {
+        let wallet = Wallet::new();
+        let genesis =
+            Block::genesis(&[Transaction::allocation(wallet.address(), 100_000.into(), 0)])
+                .unwrap();
+        let mut blockchain = Blockchain::new(genesis, params).unwrap();
+        let mut miner = Miner::new(wallet);
+        for _ in 0..nb_blocks {
+            let txs = vec![miner.build_coinbase(&blockchain)];
+            let header = miner
+                .mine_next_block(&blockchain, &txs, Some(u64::MAX))
+                .unwrap();
+            let block = blockchain.build_block_candidate(&header, &txs).unwrap();
+            blockchain.add_block(&block).unwrap();
+        }
+        blockchain
+    }
+
+    fn get_body(blockchain: &Blockchain, block_hash: &str) -> BlockBody {
+        BlockBody {
+            block_hash: block_hash.to_string(),
+            txs: blockchain
+                .get_block_by_hash(block_hash)
+                .unwrap()
+                .txs
+                .values()
+                .cloned()
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_validate_headers() {
+        // Quickly mined blocks get the target retargeted
+        let params = ConsensusParams {
+            difficulty_adjustment_interval: 4,
+            ..get_test_params()
+        };
+        let remote = get_test_chain_with(9, params.clone());
+        let genesis = remote.get_block(0).unwrap();
+        let local = Blockchain::new(genesis.clone(), params).unwrap();
+        let headers = get_headers_after(&remote, std::slice::from_ref(&genesis.block_hash), 10);
+        assert_eq!(headers.len(), 9);
+        assert_ne!(headers[8].bits, headers[0].bits);
+        assert!(validate_headers(&local, &headers, 0).is_ok());
+        assert!(validate_headers(&local, &headers, 5).is_ok());
+
+        let mut unlinked = headers.clone();
+        unlinked.remove(1);
+        assert_eq!(
+            validate_headers(&local, &unlinked, 0),
+            Err(MiningError::InvalidPrevHash)
+        );
+        let mut forged = headers.clone();
+        forged[2].bits = 0x03000001;
+        assert_eq!(
+            validate_headers(&local, &forged, 0),
+            Err(MiningError::InvalidProofOfWork)
+        );
+        // Any hash meets that target, only the retarget rules reject it
+        let mut easier = headers.clone();
+        easier[8].bits = 0x21010000;
+        assert_eq!(
+            validate_headers(&local, &easier, 5),
+            Err(MiningError::InvalidDifficulty {
+                expected: headers[8].bits,
+                got: 0x21010000
+            })
+        );
+    }
+
+    #[test]
+    fn test_peer_headers_are_capped() {
+        let remote = get_test_chain(12);
+        let genesis = remote.get_block(0).unwrap().clone();
+        let local = Blockchain::new(genesis, get_test_params()).unwrap();
+        let peer: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let mut sync = HeaderSync::new();
+        sync.max_peer_headers = 8;
+
+        let locator = get_locator(&local);
+        assert!(sync
+            .add_headers(&local, peer, get_headers_after(&remote, &locator, 5))
+            .unwrap());
+        let next = vec![remote.get_block(5).unwrap().block_hash.clone()];
+        assert!(!sync
+            .add_headers(&local, peer, get_headers_after(&remote, &next, 5))
+            .unwrap());
+        assert!(sync.select_target(&local, peer));
+        assert_eq!(sync.target.len(), 8);
+    }
+
+    #[test]
+    fn test_locator_finds_fork_point() {
+        let blockchain = get_test_chain(15);
+        let locator = get_locator(&blockchain);
+        assert_eq!(locator[0], blockchain.get_last_block().block_hash);
+        assert_eq!(
+            locator.last(),
+            Some(&blockchain.get_block(0).unwrap().block_hash)
+        );
+        assert!(locator.len() < 15);
+
+        let headers = get_headers_after(&blockchain, &locator[3..], 100);
+        assert_eq!(headers.first().unwrap().block_number, 13);
+        assert_eq!(headers.last().unwrap().block_number, 15);
+    }
+
+    #[test]
+    fn test_sync_downloads_best_header_chain() {
+        let remote = get_test_chain(20);
+        let genesis = remote.get_block(0).unwrap().clone();
+        let local = Blockchain::new(genesis, get_test_params()).unwrap();
+        let peers: Vec<SocketAddr> = vec![
+            "127.0.0.1:1".parse().unwrap(),
+            "127.0.0.1:2".parse().unwrap(),
+        ];
+
+        let mut sync = HeaderSync::new();
+        let locator = get_locator(&local);
+        for peer in peers.iter() {
+            let headers = get_headers_after(&remote, &locator, 10);
+            sync.add_headers(&local, *peer, headers).unwrap();
+            let next = vec![remote.get_block(10).unwrap().block_hash.clone()];
+            sync.add_headers(&local, *peer, get_headers_after(&remote, &next, 10))
+                .unwrap();
+            assert!(sync.select_target(&local, *peer));
+        }
+
+        // Bodies are spread over both peers
+        let requests = sync.next_requests();
+        assert_eq!(requests.len(), 2);
+        assert_ne!(requests[0].0, requests[1].0);
+        assert!(sync.next_requests().is_empty());
+
+        let mut bad_body = get_body(&remote, &requests[0].1[0]);
+        bad_body.txs = get_body(&remote, &requests[0].1[1]).txs;
+        assert!(sync.add_body(bad_body).is_err());
+        for block_hash in requests.iter().flat_map(|(_, hashes)| hashes).rev() {
+            let _ = sync.add_body(get_body(&remote, block_hash));
+        }
+        // The rejected body is requested again, nothing can be connected before it
+        assert!(sync.take_ready_blocks().is_empty());
+        let retry = sync.next_requests();
+        assert_eq!(retry[0].1, vec![requests[0].1[0].clone()]);
+        sync.add_body(get_body(&remote, &retry[0].1[0])).unwrap();
+
+        let mut local = local;
+        for block in sync.take_ready_blocks() {
+            local.add_block(&block).unwrap();
+        }
+        assert_eq!(
+            local.get_last_block().block_hash,
+            remote.get_last_block().block_hash
+        );
+        assert!(!sync.is_syncing());
+    }
+}
@@ -0,0 +1,98 @@
+use num_bigint::BigUint;
+
+/// Easiest target allowed, ~2^255: about every other hash is valid.
+pub const POW_LIMIT_BITS: u32 = 0x207fffff;
+/// ~2^244, i.e. the former 3 leading hex zeros.
+pub const BASE_MINING_BITS: u32 = 0x1f0fffff;
+
+/// Decodes a compact "bits" value (as in Bitcoin headers) into a 256-bit target.
+///
+/// The highest byte is the size of the target in bytes and the 3 lower bytes
+/// its most significant digits. Negative targets decode to zero.
+pub fn compact_to_target(bits: u32) -> BigUint {
+    let size = bits >> 24;
+    let mantissa = bits & 0x007fffff;
+    if bits & 0x00800000 != 0 {
+        return BigUint::ZERO;
+    }
+    if size <= 3 {
+        BigUint::from(mantissa >> (8 * (3 - size)))
+    } else {
+        BigUint::from(mantissa) << (8 * (size - 3))
+    }
+}
+
+/// Encodes a target into its compact "bits" form, losing the lower digits.
+pub fn target_to_compact(target: &BigUint) -> u32 {
+    let mut size = target.bits().div_ceil(8) as u32;
+    let mut compact = if size <= 3 {
+        let digits = target.iter_u32_digits().next().unwrap_or(0);
+        digits << (8 * (3 - size))
+    } else {
+        let digits: BigUint = target >> (8 * (size - 3));
+        digits.iter_u32_digits().next().unwrap_or(0)
+    };
+    // The mantissa is signed, keep it positive.
+    if compact & 0x00800000 != 0 {
+        compact >>= 8;
+        size += 1;
+    }
+    compact | (size << 24)
+}
+
+/// Expected number of hashes needed to reach the target: 2^256 / (target + 1).
+pub fn get_work(bits: u32) -> BigUint {
+    let target = compact_to_target(bits);
+    (BigUint::from(1u8) << 256u32) / (target + 1u8)
+}
+
+/// Work of the target relative to the easiest one, `POW_LIMIT_BITS` being 1.
+pub fn get_difficulty(bits: u32) -> BigUint {
+    get_work(bits) / get_work(POW_LIMIT_BITS)
+}
+
+/// Checks a hex encoded hash against the target encoded by `bits`.
+pub fn hash_meets_target(hash: &str, bits: u32) -> bool {
+    match BigUint::parse_bytes(hash.as_bytes(), 16) {
+        Some(value) => value <= compact_to_target(bits),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compact_roundtrip() {
+        for bits in [
+            POW_LIMIT_BITS,
+            BASE_MINING_BITS,
+            0x1d00ffff,
+            0x1b0404cb,
+            0x03123456,
+        ] {
+            assert_eq!(target_to_compact(&compact_to_target(bits)), bits);
+        }
+        assert_eq!(compact_to_target(0x01003456), BigUint::ZERO);
+        assert_eq!(compact_to_target(0x04923456), BigUint::ZERO);
+        assert_eq!(target_to_compact(&BigUint::from(0x80u32)), 0x02008000);
+    }
+
+    #[test]
+    fn test_work_grows_as_target_shrinks() {
+        assert_eq!(get_work(POW_LIMIT_BITS), BigUint::from(2u8));
+        assert!(get_work(BASE_MINING_BITS) > get_work(POW_LIMIT_BITS));
+        assert_eq!(get_work(BASE_MINING_BITS), BigUint::from(4096u32));
+        assert_eq!(get_difficulty(BASE_MINING_BITS), BigUint::from(2048u32));
+    }
+
+    #[test]
+    fn test_hash_meets_target() {
+        let easy_hash = format!("0000{}", "f".repeat(60));
+        assert!(hash_meets_target(&easy_hash, BASE_MINING_BITS));
+        let hard_hash = format!("001{}", "0".repeat(61));
+        assert!(!hash_meets_target(&hard_hash, BASE_MINING_BITS));
+        assert!(!hash_meets_target("not an hash", POW_LIMIT_BITS));
+    }
+}
@@ -1,12 +1,18 @@
 use core::fmt;
 use std::collections::HashMap;
 
-use bigdecimal::BigDecimal;
+use bigdecimal::{BigDecimal, Zero};
 use serde::{Deserialize, Serialize};
 
-use crate::{block::Block, hashable::Hashable, transaction::Transaction};
+use crate::{
+    address::Address,
+    block::{Block, BlockError},
+    consensus::DEFAULT_CHAIN_ID,
+    hashable::Hashable,
+    transaction::Transaction,
+};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AccountState {
     pub balance: BigDecimal,
     pub nonce: u64,
@@ -21,7 +27,7 @@ impl Default for AccountState {
 impl AccountState {
     pub fn new() -> AccountState {
         AccountState {
-            balance: BigDecimal::from(100000),
+            balance: BigDecimal::zero(),
             nonce: 0,
         }
     }
@@ -47,9 +53,40 @@ impl fmt::Display for AccountState {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+/// Value of an account before it got touched, `None` if it did not exist.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub address: Address,
+    pub previous: Option<AccountState>,
+}
+
+/// Undo log of every account modified while the journal was recording.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct StateJournal {
+    pub entries: Vec<JournalEntry>,
+    /// Money minted by the chain up to the journaled block, genesis
+    /// allocations included, kept so the max supply is enforced in O(1).
+    #[serde(default)]
+    pub minted: BigDecimal,
+}
+
+impl StateJournal {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct State {
-    pub state: HashMap<String, AccountState>,
+    pub state: HashMap<Address, AccountState>,
+    /// Network the transactions applied must be signed for.
+    pub chain_id: u64,
+    #[serde(skip)]
+    journal: Option<StateJournal>,
 }
 
 impl Default for State {
@@ -62,75 +99,195 @@ impl State {
     pub fn new() -> State {
         State {
             state: HashMap::new(),
+            chain_id: DEFAULT_CHAIN_ID,
+            journal: None,
         }
     }
 
-    pub fn from_genesis(genesis_block: &Block) -> State {
+    pub fn from_genesis(genesis_block: &Block) -> Result<State, BlockError> {
         let mut state = State::new();
-        let block_header = genesis_block.block_header.clone();
-        assert_eq!(block_header.block_number, 0);
-        assert!(block_header.prev_hash == "0");
+        let block_header = &genesis_block.block_header;
+        if block_header.block_number != 0 || block_header.prev_hash != "0" {
+            return Err(BlockError::InvalidGenesis);
+        }
+        // The allocations are the only money not coming from mining rewards
         for tx in genesis_block.txs.values() {
-            state.register_multiple_addresses(vec![&tx.from, &tx.to]);
-            assert!(tx.is_correctly_signed());
-            state.get_mut(&tx.from).increment_nonce();
+            if !tx.is_allocation() {
+                return Err(BlockError::InvalidGenesis);
+            }
+            state.register_address(&tx.to);
             state.get_mut(&tx.to).add_balance(&tx.value);
         }
-        state
+        Ok(state)
     }
 
-    pub fn apply_tx(&mut self, tx: &Transaction, miner_address: &str) {
-        self.register_multiple_addresses(vec![&tx.from, &tx.to]);
-        if tx.from != tx.to {
-            assert!(tx.is_correctly_signed());
-            let from_state = self.state.get(&tx.from).unwrap();
-            assert!(from_state.nonce == tx.nonce);
-            assert!(&from_state.balance - &tx.value > BigDecimal::from(0));
-        } else {
-            assert!(tx.from == miner_address);
+    /// Applies a signed transfer. Its fee is left for the coinbase of the block.
+    pub fn apply_tx(&mut self, tx: &Transaction) -> Result<(), BlockError> {
+        if tx.value < BigDecimal::zero() || tx.fee < BigDecimal::zero() {
+            return Err(BlockError::NegativeAmount);
+        }
+        if tx.is_coinbase() {
+            return Err(BlockError::UnauthorizedCoinbase);
+        }
+        if tx.chain_id != self.chain_id {
+            return Err(BlockError::WrongChain {
+                expected: self.chain_id,
+                got: tx.chain_id,
+            });
+        }
+        if !tx.is_correctly_signed(self.chain_id) {
+            return Err(BlockError::InvalidSignature);
+        }
+        // Accounts are only created once the transfer is known to be valid
+        let from_state = self.get_account(&tx.from);
+        if from_state.nonce != tx.nonce {
+            return Err(BlockError::NonceMismatch {
+                expected: from_state.nonce,
+                got: tx.nonce,
+            });
         }
+        if &from_state.balance - tx.get_cost() <= BigDecimal::zero() {
+            return Err(BlockError::InsufficientBalance);
+        }
+        self.register_multiple_addresses(vec![&tx.from, &tx.to]);
         self.get_mut(&tx.from).increment_nonce();
-        self.get_mut(&tx.from).sub_balance(&tx.value);
+        self.get_mut(&tx.from).sub_balance(&tx.get_cost());
         self.get_mut(&tx.to).add_balance(&tx.value);
-        self.get_mut(miner_address).add_balance(&tx.fee);
+        Ok(())
+    }
+
+    /// Applies every transfer of the block then its coinbase, which must pay
+    /// `miner_address` the reward plus the fees of the block, less
+    /// `burned_fees`.
+    ///
+    /// Either everything is applied and the undo log of the block is returned,
+    /// or nothing is and the state is left untouched.
+    pub fn apply_block(
+        &mut self,
+        block: &Block,
+        miner_address: &Address,
+        reward: &BigDecimal,
+        burned_fees: &BigDecimal,
+    ) -> Result<StateJournal, BlockError> {
+        self.start_journal();
+        if let Err(err) = self.apply_block_txs(block, miner_address, reward, burned_fees) {
+            let journal = self.take_journal();
+            self.revert(journal);
+            return Err(err);
+        }
+        Ok(self.take_journal())
+    }
+
+    fn apply_block_txs(
+        &mut self,
+        block: &Block,
+        miner_address: &Address,
+        reward: &BigDecimal,
+        burned_fees: &BigDecimal,
+    ) -> Result<(), BlockError> {
+        let mut txs = block.txs.values();
+        let coinbase = txs
+            .next()
+            .filter(|tx| tx.is_coinbase())
+            .ok_or(BlockError::MissingCoinbase)?;
+        if coinbase.chain_id != self.chain_id {
+            return Err(BlockError::WrongChain {
+                expected: self.chain_id,
+                got: coinbase.chain_id,
+            });
+        }
+        let mut fees = BigDecimal::zero();
+        for tx in txs {
+            self.apply_tx(tx)?;
+            fees += &tx.fee;
+        }
+        if &coinbase.to != miner_address
+            || coinbase.value != reward + fees - burned_fees
+            || !coinbase.fee.is_zero()
+        {
+            return Err(BlockError::InvalidCoinbase);
+        }
+        self.apply_mining_reward(&coinbase.to, &coinbase.value);
+        Ok(())
+    }
+
+    /// Starts recording the previous value of every account modified.
+    pub fn start_journal(&mut self) {
+        self.journal = Some(StateJournal::default());
+    }
+
+    /// Stops recording and returns what has been recorded so far.
+    pub fn take_journal(&mut self) -> StateJournal {
+        self.journal.take().unwrap_or_default()
     }
 
-    pub fn apply_mining_reward(&mut self, miner_address: &str, reward: &BigDecimal) {
+    /// Restores every account recorded in the journal to its previous value.
+    pub fn revert(&mut self, journal: StateJournal) {
+        for entry in journal.entries.into_iter().rev() {
+            match entry.previous {
+                Some(account) => self.state.insert(entry.address, account),
+                None => self.state.remove(&entry.address),
+            };
+        }
+    }
+
+    fn record(&mut self, address: &Address) {
+        if let Some(journal) = self.journal.as_mut() {
+            journal.entries.push(JournalEntry {
+                address: *address,
+                previous: self.state.get(address).cloned(),
+            });
+        }
+    }
+
+    pub fn apply_mining_reward(&mut self, miner_address: &Address, reward: &BigDecimal) {
+        self.register_address(miner_address);
         self.get_mut(miner_address).add_balance(reward);
     }
 
-    pub fn register_address(&mut self, public_key: &str) {
-        if self.is_new(public_key) {
-            self.state
-                .insert(public_key.to_string(), AccountState::new());
+    pub fn register_address(&mut self, address: &Address) {
+        if self.is_new(address) {
+            self.record(address);
+            self.state.insert(*address, AccountState::new());
         }
     }
 
-    pub fn register_multiple_addresses(&mut self, public_keys: Vec<&str>) {
-        for public_key in public_keys {
-            self.register_address(public_key)
+    pub fn register_multiple_addresses(&mut self, addresses: Vec<&Address>) {
+        for address in addresses {
+            self.register_address(address)
         }
     }
 
-    pub fn is_new(&self, public_key: &str) -> bool {
-        !self.state.contains_key(public_key)
+    pub fn is_new(&self, address: &Address) -> bool {
+        !self.state.contains_key(address)
     }
 
-    pub fn get(&self, address: &str) -> &AccountState {
+    pub fn get(&self, address: &Address) -> &AccountState {
         self.state.get(address).unwrap()
     }
 
-    pub fn get_mut(&mut self, address: &str) -> &mut AccountState {
+    /// Account of an address, a fresh one if it never appeared on chain.
+    pub fn get_account(&self, address: &Address) -> AccountState {
+        self.state.get(address).cloned().unwrap_or_default()
+    }
+
+    pub fn get_mut(&mut self, address: &Address) -> &mut AccountState {
+        self.record(address);
         self.state.get_mut(address).unwrap()
     }
 
-    pub fn get_nonce(&self, address: &str) -> u64 {
+    pub fn get_nonce(&self, address: &Address) -> u64 {
         self.get(address).nonce
     }
 
-    pub fn get_balance(&self, address: &str) -> BigDecimal {
+    pub fn get_balance(&self, address: &Address) -> BigDecimal {
         self.get(address).balance.clone()
     }
+
+    /// Sum of the balances of every account.
+    pub fn get_total_supply(&self) -> BigDecimal {
+        self.state.values().map(|account| &account.balance).sum()
+    }
 }
 
 impl Hashable for State {}
@@ -144,3 +301,315 @@ impl fmt::Display for State {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::BlockHeader;
+    use crate::merkle_tree::MerkleTree;
+    use crate::wallet::Wallet;
+
+    fn get_test_state(wallet: &Wallet) -> State {
+        let txs = [Transaction::allocation(wallet.address(), 100_000.into(), 0)];
+        State::from_genesis(&Block::genesis(&txs).unwrap()).unwrap()
+    }
+
+    /// Block #1 holding the transactions behind a coinbase paying miner.eth.
+    fn get_test_block(coinbase_value: u64, mut txs: Vec<Transaction>) -> Block {
+        let coinbase = Transaction::coinbase(Address::named("miner.eth"), coinbase_value.into(), 1);
+        txs.insert(0, coinbase);
+        let merkle_root = MerkleTree::new(&txs).get_root();
+        Block::new(
+            BlockHeader::new(merkle_root, "0", 1, txs.len() as u64),
+            &txs,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_apply_tx_rejects_nonce_mismatch() {
+        let mut wallet = Wallet::new();
+        let mut state = get_test_state(&wallet);
+        wallet.nonce += 1;
+        let tx = wallet.send(Address::named("adel.eth"), BigDecimal::from(1));
+        assert_eq!(
+            state.apply_tx(&tx),
+            Err(BlockError::NonceMismatch {
+                expected: 0,
+                got: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_apply_tx_rejects_bad_signature() {
+        let mut wallet = Wallet::new();
+        let mut state = get_test_state(&wallet);
+        let mut tx = wallet.send(Address::named("adel.eth"), BigDecimal::from(1));
+        tx.value = BigDecimal::from(42);
+        assert_eq!(state.apply_tx(&tx), Err(BlockError::InvalidSignature));
+    }
+
+    #[test]
+    fn test_apply_tx_rejects_other_chain() {
+        let mut wallet = Wallet::new();
+        let mut state = get_test_state(&wallet);
+        wallet.chain_id = 1337;
+        let tx = wallet.send(Address::named("adel.eth"), BigDecimal::from(1));
+        assert_eq!(
+            state.apply_tx(&tx),
+            Err(BlockError::WrongChain {
+                expected: DEFAULT_CHAIN_ID,
+                got: 1337
+            })
+        );
+
+        state.chain_id = 1337;
+        assert!(state.apply_tx(&tx).is_ok());
+    }
+
+    #[test]
+    fn test_apply_tx_rejects_insufficient_balance() {
+        let mut wallet = Wallet::new();
+        let mut state = get_test_state(&wallet);
+        let tx = wallet.send(Address::named("adel.eth"), BigDecimal::from(1_000_000));
+        assert_eq!(state.apply_tx(&tx), Err(BlockError::InsufficientBalance));
+    }
+
+    #[test]
+    fn test_apply_tx_rejects_negative_amounts() {
+        let mut wallet = Wallet::new();
+        let mut state = get_test_state(&wallet);
+        let accounts_before = state.state.clone();
+        let tx = wallet.send(Address::named("adel.eth"), BigDecimal::from(-1000));
+        assert_eq!(state.apply_tx(&tx), Err(BlockError::NegativeAmount));
+        let tx = wallet.send_with_fee(
+            Address::named("adel.eth"),
+            BigDecimal::from(1),
+            BigDecimal::from(-1),
+        );
+        assert_eq!(state.apply_tx(&tx), Err(BlockError::NegativeAmount));
+        assert_eq!(state.state, accounts_before);
+    }
+
+    #[test]
+    fn test_rejected_tx_creates_no_account() {
+        let mut wallet = Wallet::new();
+        let mut state = get_test_state(&wallet);
+        let accounts_before = state.state.clone();
+        let tx = wallet.send(Address::named("adel.eth"), BigDecimal::from(1_000_000));
+        assert_eq!(state.apply_tx(&tx), Err(BlockError::InsufficientBalance));
+        let tx = Wallet::new().send(Address::named("adel.eth"), BigDecimal::from(1));
+        assert_eq!(state.apply_tx(&tx), Err(BlockError::InsufficientBalance));
+        assert_eq!(state.state, accounts_before);
+    }
+
+    #[test]
+    fn test_apply_tx_rejects_coinbase() {
+        let mut wallet = Wallet::new();
+        let mut state = get_test_state(&wallet);
+        let coinbase = Transaction::coinbase(wallet.address(), 1.into(), 1);
+        assert_eq!(
+            state.apply_tx(&coinbase),
+            Err(BlockError::UnauthorizedCoinbase)
+        );
+
+        // A self transfer is a plain transfer, paying its fee
+        let tx = wallet.send_with_fee(wallet.address(), BigDecimal::from(5), BigDecimal::from(1));
+        assert!(state.apply_tx(&tx).is_ok());
+        assert_eq!(
+            state.get_balance(&wallet.address()),
+            BigDecimal::from(99_999)
+        );
+        assert_eq!(state.get_nonce(&wallet.address()), 1);
+    }
+
+    #[test]
+    fn test_apply_coinbase_keeps_miner_nonce() {
+        let wallet = Wallet::new();
+        let mut state = get_test_state(&wallet);
+        let block = get_test_block(10, vec![]);
+        state
+            .apply_block(
+                &block,
+                &Address::named("miner.eth"),
+                &BigDecimal::from(10),
+                &BigDecimal::zero(),
+            )
+            .unwrap();
+        assert_eq!(
+            state.get_balance(&Address::named("miner.eth")),
+            BigDecimal::from(10)
+        );
+        assert_eq!(state.get_nonce(&Address::named("miner.eth")), 0);
+    }
+
+    #[test]
+    fn test_apply_tx_updates_balances() {
+        let mut wallet = Wallet::new();
+        let mut state = get_test_state(&wallet);
+        let supply = state.get_total_supply();
+        let tx = wallet.send_with_fee(
+            Address::named("adel.eth"),
+            BigDecimal::from(7),
+            BigDecimal::from(2),
+        );
+        assert!(state.apply_tx(&tx).is_ok());
+        assert_eq!(
+            state.get_balance(&wallet.address()),
+            BigDecimal::from(100_000 - 7 - 2)
+        );
+        assert_eq!(
+            state.get_balance(&Address::named("adel.eth")),
+            BigDecimal::from(7)
+        );
+        assert_eq!(state.get_nonce(&wallet.address()), 1);
+        // The fee is left for the coinbase
+        assert_eq!(state.get_total_supply(), supply - BigDecimal::from(2));
+    }
+
+    #[test]
+    fn test_genesis_only_holds_allocations() {
+        let mut wallet = Wallet::new();
+        let state = get_test_state(&wallet);
+        assert_eq!(state.get_total_supply(), BigDecimal::from(100_000));
+        assert_eq!(
+            state.get_account(&Address::named("adel.eth")).balance,
+            BigDecimal::zero()
+        );
+
+        let txs = [wallet.send(Address::named("adel.eth"), BigDecimal::from(1))];
+        assert_eq!(
+            State::from_genesis(&Block::genesis(&txs).unwrap()).err(),
+            Some(BlockError::InvalidGenesis)
+        );
+    }
+
+    #[test]
+    fn test_apply_block_is_all_or_nothing() {
+        let mut wallet = Wallet::new();
+        let mut state = get_test_state(&wallet);
+        let accounts_before = state.state.clone();
+
+        let mut txs = wallet.sign_random_txs(Address::named("aihe.eth"), 3);
+        wallet.nonce += 1;
+        txs.push(wallet.send(Address::named("aihe.eth"), BigDecimal::from(1)));
+        let block = get_test_block(1, txs);
+        assert_eq!(
+            state
+                .apply_block(
+                    &block,
+                    &Address::named("miner.eth"),
+                    &BigDecimal::from(1),
+                    &BigDecimal::zero()
+                )
+                .err(),
+            Some(BlockError::NonceMismatch {
+                expected: 3,
+                got: 4
+            })
+        );
+        assert_eq!(state.state, accounts_before);
+    }
+
+    #[test]
+    fn test_revert_block_journal() {
+        let mut wallet = Wallet::new();
+        let mut state = get_test_state(&wallet);
+        let accounts_before = state.state.clone();
+
+        let block = get_test_block(1, wallet.sign_random_txs(Address::named("aihe.eth"), 3));
+        let journal = state
+            .apply_block(
+                &block,
+                &Address::named("miner.eth"),
+                &BigDecimal::from(1),
+                &BigDecimal::zero(),
+            )
+            .unwrap();
+        assert_eq!(
+            state.get_balance(&Address::named("miner.eth")),
+            BigDecimal::from(1)
+        );
+        state.revert(journal);
+        assert_eq!(state.state, accounts_before);
+    }
+
+    #[test]
+    fn test_apply_block_checks_coinbase() {
+        let mut wallet = Wallet::new();
+        let mut state = get_test_state(&wallet);
+        let accounts_before = state.state.clone();
+        let tx = wallet.send_with_fee(
+            Address::named("aihe.eth"),
+            BigDecimal::from(5),
+            BigDecimal::from(2),
+        );
+        let reward = BigDecimal::from(10);
+        let burned_fees = BigDecimal::from(1);
+
+        let block = get_test_block(12, vec![tx.clone()]);
+        assert_eq!(
+            state
+                .apply_block(&block, &Address::named("miner.eth"), &reward, &burned_fees)
+                .err(),
+            Some(BlockError::InvalidCoinbase)
+        );
+        assert_eq!(state.state, accounts_before);
+        let block = get_test_block(11, vec![tx.clone()]);
+        assert_eq!(
+            state
+                .apply_block(&block, &Address::named("other.eth"), &reward, &burned_fees)
+                .err(),
+            Some(BlockError::InvalidCoinbase)
+        );
+        assert!(state
+            .apply_block(&block, &Address::named("miner.eth"), &reward, &burned_fees)
+            .is_ok());
+        assert_eq!(
+            state.get_balance(&Address::named("miner.eth")),
+            BigDecimal::from(11)
+        );
+        assert_eq!(state.get_total_supply(), BigDecimal::from(100_009));
+
+        let header = |txs: &[Transaction]| {
+            BlockHeader::new(MerkleTree::new(txs).get_root(), "0", 1, txs.len() as u64)
+        };
+        let coinbase = Transaction::coinbase(Address::named("miner.eth"), 1.into(), 1);
+        let txs = [tx.clone(), coinbase.clone()];
+        assert_eq!(
+            Block::new(header(&txs), &txs).err(),
+            Some(BlockError::MissingCoinbase)
+        );
+        let txs = [
+            coinbase.clone(),
+            tx,
+            Transaction::coinbase(Address::named("miner.eth"), 1.into(), 2),
+        ];
+        assert_eq!(
+            Block::new(header(&txs), &txs).err(),
+            Some(BlockError::MultipleCoinbases)
+        );
+        let txs = [Transaction::coinbase(
+            Address::named("miner.eth"),
+            1.into(),
+            2,
+        )];
+        assert_eq!(
+            Block::new(header(&txs), &txs).err(),
+            Some(BlockError::InvalidCoinbase)
+        );
+
+        let block = get_test_block(9, Vec::new());
+        state.chain_id = 1337;
+        assert_eq!(
+            state
+                .apply_block(&block, &Address::named("miner.eth"), &reward, &burned_fees)
+                .err(),
+            Some(BlockError::WrongChain {
+                expected: 1337,
+                got: DEFAULT_CHAIN_ID
+            })
+        );
+    }
+}
@@ -8,18 +8,34 @@ use rand::distributions::{Distribution, Standard};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 
+use crate::address::Address;
+use crate::consensus::DEFAULT_CHAIN_ID;
 use crate::hashable::Hashable;
 use crate::utils::to_readable_hash;
 
+/// Format of the signed payload, bumped whenever it changes.
+pub const TX_VERSION: u32 = 1;
+
+/// Sender of the genesis allocations, which are neither signed nor from a key.
+pub const ALLOCATION_SENDER: Address = Address::from_bytes([0; 20]);
+/// Sender of the coinbase transactions, which mint the block reward & fees.
+pub const COINBASE_SENDER: Address = Address::from_bytes([0xff; 20]);
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
-    pub from: String,
-    pub to: String,
+    pub version: u32,
+    /// Network the transaction is meant for, so it can't be replayed on another.
+    pub chain_id: u64,
+    pub from: Address,
+    pub to: Address,
     pub value: BigDecimal,
     pub fee: BigDecimal,
     pub time: i64,
     pub nonce: u64,
     pub signed: bool,
+    /// Compressed public key of the sender, hex encoded, which must hash to
+    /// the `from` address.
+    pub public_key: Option<String>,
     pub signature: Option<Signature>,
 }
 
@@ -27,8 +43,10 @@ pub struct Transaction {
 // the signature fields.
 #[derive(Serialize)]
 struct HashableTransaction<'a> {
-    from: &'a String,
-    to: &'a String,
+    version: u32,
+    chain_id: u64,
+    from: &'a Address,
+    to: &'a Address,
     value: &'a BigDecimal,
     fee: &'a BigDecimal,
     time: i64,
@@ -49,13 +67,15 @@ impl Hashable for Transaction {
 
 impl Transaction {
     pub fn new(
-        from: String,
-        to: String,
+        from: Address,
+        to: Address,
         value: BigDecimal,
         fee: BigDecimal,
         nonce: u64,
     ) -> Transaction {
         Transaction {
+            version: TX_VERSION,
+            chain_id: DEFAULT_CHAIN_ID,
             from,
             to,
             value,
@@ -63,33 +83,71 @@ impl Transaction {
             time: Utc::now().timestamp(),
             signed: false,
             nonce,
+            public_key: None,
             signature: Option::None,
         }
     }
 
-    pub fn is_correctly_signed(&self) -> bool {
-        if self.signature.is_none() {
-            return false;
+    /// Unsigned transaction crediting `to` in the genesis block.
+    pub fn allocation(to: Address, value: BigDecimal, time: i64) -> Transaction {
+        Transaction {
+            time,
+            ..Transaction::new(ALLOCATION_SENDER, to, value, BigDecimal::from(0), 0)
         }
-        let from_address = if self.from.starts_with("0x") {
-            &self.from[2..]
-        } else {
-            &self.from
-        };
-        let from_bytes = match hex::decode(from_address) {
-            Ok(bytes) => bytes,
-            Err(_) => return false,
+    }
+
+    /// Unsigned transaction paying the miner of block `block_number`. The
+    /// block number is used as nonce so that every coinbase has its own hash.
+    pub fn coinbase(miner_address: Address, value: BigDecimal, block_number: u64) -> Transaction {
+        Transaction::new(
+            COINBASE_SENDER,
+            miner_address,
+            value,
+            BigDecimal::from(0),
+            block_number,
+        )
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        self.from == COINBASE_SENDER && self.signature.is_none()
+    }
+
+    /// What the sender pays: the value sent and the fee.
+    pub fn get_cost(&self) -> BigDecimal {
+        &self.value + &self.fee
+    }
+
+    pub fn is_allocation(&self) -> bool {
+        self.from == ALLOCATION_SENDER && self.signature.is_none()
+    }
+
+    /// Whether the transaction is signed by its sender for the `chain_id`
+    /// network, in the current format.
+    pub fn is_correctly_signed(&self, chain_id: u64) -> bool {
+        self.chain_id == chain_id && self.version == TX_VERSION && self.is_signature_valid()
+    }
+
+    /// Whether the signature is made by the key of the sender address,
+    /// whatever the network.
+    pub fn is_signature_valid(&self) -> bool {
+        let (Some(public_key), Some(signature)) = (&self.public_key, &self.signature) else {
+            return false;
         };
-        let signer = match VerifyingKey::from_sec1_bytes(&from_bytes) {
-            Ok(key) => key,
-            Err(_) => return false,
+        let signer = match hex::decode(public_key)
+            .ok()
+            .and_then(|bytes| VerifyingKey::from_sec1_bytes(&bytes).ok())
+        {
+            Some(key) => key,
+            None => return false,
         };
-        let signature = self.signature.unwrap();
-        signer.verify(&self.to_bytes(), &signature).is_ok()
+        Address::from_public_key(&signer) == self.from
+            && signer.verify(&self.to_bytes(), signature).is_ok()
     }
 
-    fn to_hashable(&self) -> HashableTransaction {
+    fn to_hashable(&self) -> HashableTransaction<'_> {
         HashableTransaction {
+            version: self.version,
+            chain_id: self.chain_id,
             from: &self.from,
             to: &self.to,
             value: &self.value,
@@ -108,7 +166,8 @@ impl fmt::Display for Transaction {
         };
         write!(
             f,
-            r#"        fr:                     {}
+            r#"        chain_id:               {}
+        fr:                     {}
         to:                     {}
         value:                  {}
         fee:                    {}
@@ -118,8 +177,9 @@ impl fmt::Display for Transaction {
         hash:                   {}
         signature:              {}
         "#,
-            to_readable_hash(&self.from),
-            to_readable_hash(&self.to),
+            self.chain_id,
+            to_readable_hash(&self.from.to_string()),
+            to_readable_hash(&self.to.to_string()),
             self.value,
             self.fee,
             self.nonce,
@@ -134,13 +194,16 @@ impl fmt::Display for Transaction {
 impl Distribution<Transaction> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Transaction {
         Transaction {
-            from: rng.gen::<u64>().to_string(),
-            to: rng.gen::<u64>().to_string(),
+            version: TX_VERSION,
+            chain_id: DEFAULT_CHAIN_ID,
+            from: Address::from_bytes(rng.gen()),
+            to: Address::from_bytes(rng.gen()),
             value: BigDecimal::from(rng.gen::<u64>()),
             fee: BigDecimal::from(rng.gen::<u64>()),
             time: Utc::now().timestamp(),
             nonce: rng.gen(),
             signed: false,
+            public_key: None,
             signature: Option::None,
         }
     }
@@ -154,8 +217,8 @@ mod tests {
     #[test]
     fn test_sign_does_not_update_hash() {
         let mut tx = Transaction::new(
-            String::from("aihe.eth"),
-            String::from("aihe.eth"),
+            Address::named("aihe.eth"),
+            Address::named("aihe.eth"),
             BigDecimal::from(3000),
             BigDecimal::from(1),
             0,
@@ -169,14 +232,14 @@ mod tests {
     #[test]
     fn test_changing_field_update_hash() {
         let mut tx = Transaction::new(
-            String::from("aihe.eth"),
-            String::from("aihe.eth"),
+            Address::named("aihe.eth"),
+            Address::named("aihe.eth"),
             BigDecimal::from(3000),
             BigDecimal::from(1),
             0,
         );
         let first_hash = tx.get_hash();
-        tx.to = String::from("azurwastaken.eth");
+        tx.to = Address::named("azurwastaken.eth");
         let new_hash = tx.get_hash();
         assert_ne!(first_hash, new_hash)
     }
@@ -184,26 +247,57 @@ mod tests {
     #[test]
     fn test_tx_correctly_signed() {
         let mut my_wallet = Wallet::new();
-        let tx = my_wallet.send("adel.eth", BigDecimal::from(42));
-        assert_eq!(tx.signed, true);
-        assert_eq!(tx.is_correctly_signed(), true);
+        let tx = my_wallet.send(Address::named("adel.eth"), BigDecimal::from(42));
+        assert!(tx.signed);
+        assert!(tx.is_correctly_signed(DEFAULT_CHAIN_ID));
+    }
+
+    #[test]
+    fn test_tx_signed_for_another_chain() {
+        let mut my_wallet = Wallet::new();
+        my_wallet.chain_id = 1337;
+        let mut tx = my_wallet.send(Address::named("adel.eth"), BigDecimal::from(42));
+        assert_eq!(tx.chain_id, 1337);
+        assert!(tx.is_correctly_signed(1337));
+        assert!(!tx.is_correctly_signed(DEFAULT_CHAIN_ID));
+
+        // Replaying it elsewhere takes changing the signed chain ID
+        tx.chain_id = DEFAULT_CHAIN_ID;
+        assert!(!tx.is_correctly_signed(DEFAULT_CHAIN_ID));
     }
 
     #[test]
     fn test_tx_not_correctly_signed() {
         let mut my_wallet = Wallet::new();
-        let mut tx = my_wallet.send("adel.eth", BigDecimal::from(42));
-        assert_eq!(tx.signed, true);
-        tx.from = String::from("new_sender.eth");
-        assert_eq!(tx.is_correctly_signed(), false);
+        let mut tx = my_wallet.send(Address::named("adel.eth"), BigDecimal::from(42));
+        assert!(tx.signed);
+        tx.from = Address::named("new_sender.eth");
+        assert!(!tx.is_correctly_signed(DEFAULT_CHAIN_ID));
+    }
+
+    #[test]
+    fn test_tx_signed_with_key_of_another_address() {
+        let victim = Wallet::new();
+        let thief = Wallet::new();
+        let tx = Transaction::new(
+            victim.address(),
+            thief.address(),
+            BigDecimal::from(42),
+            BigDecimal::from(0),
+            0,
+        );
+        // A valid signature, but not by the key behind the sender address
+        let tx = thief.sign(tx);
+        assert!(!tx.is_correctly_signed(DEFAULT_CHAIN_ID));
+        assert!(victim.sign(tx).is_correctly_signed(DEFAULT_CHAIN_ID));
     }
 
     #[test]
     fn test_tx_not_correctly_signed_after_update() {
         let mut my_wallet = Wallet::new();
-        let mut tx = my_wallet.send("adel.eth", BigDecimal::from(42));
-        assert_eq!(tx.signed, true);
+        let mut tx = my_wallet.send(Address::named("adel.eth"), BigDecimal::from(42));
+        assert!(tx.signed);
         tx.value = BigDecimal::from(69420);
-        assert_eq!(tx.is_correctly_signed(), false);
+        assert!(!tx.is_correctly_signed(DEFAULT_CHAIN_ID));
     }
 }
@@ -44,10 +44,3 @@ pub fn hash_to_bytes(hash: &str) -> Vec<u8> {
 pub fn get_rand_txs(n: usize) -> Vec<Transaction> {
     (0..n).map(|_| rand::random::<Transaction>()).collect()
 }
-
-pub fn check_prefix(string: &str, letter: char, n: usize) -> bool {
-    if string.len() < n {
-        return false;
-    }
-    string.chars().take(n).all(|c| c == letter)
-}
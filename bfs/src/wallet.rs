@@ -1,9 +1,17 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
 use bigdecimal::BigDecimal;
 use k256::ecdsa::{signature::Signer, Signature, SigningKey, VerifyingKey};
 use rand::Rng;
 use rand_core::OsRng;
+use serde::{Deserialize, Serialize};
 
+use crate::address::Address;
+use crate::consensus::DEFAULT_CHAIN_ID;
 use crate::hashable::Hashable;
+use crate::keystore::{KdfParams, Keystore, KeystoreError};
 use crate::transaction::Transaction;
 
 #[derive(Clone)]
@@ -11,6 +19,15 @@ pub struct Wallet {
     pub public_key: VerifyingKey,
     private_key: SigningKey,
     pub nonce: u64,
+    /// Network the transactions of the wallet are signed for.
+    pub chain_id: u64,
+}
+
+/// Wallet as saved on disk, the private key being hex encoded.
+#[derive(Serialize, Deserialize)]
+struct WalletFile {
+    private_key: String,
+    nonce: u64,
 }
 
 impl Default for Wallet {
@@ -21,41 +38,98 @@ impl Default for Wallet {
 
 impl Wallet {
     pub fn new() -> Wallet {
-        let private_key = SigningKey::random(&mut OsRng);
+        Wallet::from_private_key(SigningKey::random(&mut OsRng), 0)
+    }
+
+    pub fn from_private_key(private_key: SigningKey, nonce: u64) -> Wallet {
         Wallet {
             public_key: VerifyingKey::from(&private_key),
             private_key,
-            nonce: 0,
+            nonce,
+            chain_id: DEFAULT_CHAIN_ID,
         }
     }
 
+    pub fn from_private_key_bytes(bytes: &[u8], nonce: u64) -> Option<Wallet> {
+        let private_key = SigningKey::from_slice(bytes).ok()?;
+        Some(Wallet::from_private_key(private_key, nonce))
+    }
+
+    pub(crate) fn private_key_bytes(&self) -> Vec<u8> {
+        self.private_key.to_bytes().to_vec()
+    }
+
+    /// Loads a wallet saved with `Wallet::save`.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Wallet> {
+        let wallet_file: WalletFile = serde_json::from_str(&fs::read_to_string(path)?)?;
+        hex::decode(wallet_file.private_key)
+            .ok()
+            .and_then(|bytes| Wallet::from_private_key_bytes(&bytes, wallet_file.nonce))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid private key"))
+    }
+
+    /// Saves the private key and nonce of the wallet, unencrypted.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let wallet_file = WalletFile {
+            private_key: hex::encode(self.private_key.to_bytes()),
+            nonce: self.nonce,
+        };
+        fs::write(path, serde_json::to_string_pretty(&wallet_file)?)
+    }
+
+    /// Loads a wallet saved with `Wallet::save_encrypted`.
+    pub fn load_encrypted<P: AsRef<Path>>(
+        path: P,
+        password: &str,
+    ) -> Result<Wallet, KeystoreError> {
+        Keystore::load(path)?.decrypt(password)
+    }
+
+    /// Saves the private key encrypted with `password`, and the nonce.
+    pub fn save_encrypted<P: AsRef<Path>>(
+        &self,
+        path: P,
+        password: &str,
+    ) -> Result<(), KeystoreError> {
+        Keystore::encrypt(self, password, &KdfParams::default())?.save(path)
+    }
+
     pub fn public_key(&self) -> String {
         self.public_key.to_encoded_point(true).to_string()
     }
 
+    pub fn address(&self) -> Address {
+        Address::from_public_key(&self.public_key)
+    }
+
     pub fn sign(&self, mut tx: Transaction) -> Transaction {
         let hash = tx.to_bytes();
         let signature: Signature = self.private_key.sign(&hash);
+        tx.public_key = Some(self.public_key());
         tx.signature = Some(signature);
         tx.signed = true;
         tx
     }
 
-    pub fn send(&mut self, to: &str, value: BigDecimal) -> Transaction {
-        let mut tx = Transaction::new(
-            self.public_key(),
-            to.to_string(),
-            value,
-            // TODO: fees are 0 atm
-            BigDecimal::from(0),
-            self.nonce,
-        );
+    pub fn send(&mut self, to: Address, value: BigDecimal) -> Transaction {
+        // TODO: fees are 0 atm
+        self.send_with_fee(to, value, BigDecimal::from(0))
+    }
+
+    pub fn send_with_fee(
+        &mut self,
+        to: Address,
+        value: BigDecimal,
+        fee: BigDecimal,
+    ) -> Transaction {
+        let mut tx = Transaction::new(self.address(), to, value, fee, self.nonce);
+        tx.chain_id = self.chain_id;
         tx = self.sign(tx);
         self.nonce += 1;
         tx
     }
 
-    pub fn sign_random_txs(&mut self, to: &str, n: usize) -> Vec<Transaction> {
+    pub fn sign_random_txs(&mut self, to: Address, n: usize) -> Vec<Transaction> {
         let mut rng = rand::thread_rng();
         (0..n)
             .map(|_| {
@@ -65,3 +139,25 @@ impl Wallet {
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let path = env::temp_dir().join(format!("bfs-wallet-{}.json", std::process::id()));
+        let mut wallet = Wallet::new();
+        wallet.nonce = 7;
+        wallet.save(&path).unwrap();
+
+        let loaded = Wallet::load(&path).unwrap();
+        assert_eq!(loaded.public_key(), wallet.public_key());
+        assert_eq!(loaded.nonce, 7);
+        fs::write(&path, r#"{"private_key":"00","nonce":0}"#).unwrap();
+        assert!(Wallet::load(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -29,13 +29,9 @@ class App extends Component {
   async fetchBlocks() {
     fetch(URL + "blocks")
       .then((response) => response.json())
-      .then((block) => {
-        let parsedBlocks = [];
-        JSON.parse(block["blocks"]).forEach((block) => {
-          parsedBlocks.push(JSON.parse(block));
-        });
+      .then((blocks) => {
         this.setState({
-          blocks: parsedBlocks,
+          blocks: blocks,
         });
       });
   }
@@ -43,12 +39,8 @@ class App extends Component {
   async fetchTXsFromPool() {
     fetch(URL + "txs_pool")
       .then((response) => response.json())
-      .then((tx) => {
-        let parsedTXs = [];
-        tx["txs_pool"].forEach((tx) => {
-          parsedTXs.push(JSON.parse(tx));
-        });
-        this.setState({ txs_pool: parsedTXs });
+      .then((txs) => {
+        this.setState({ txs_pool: txs });
       });
   }
 
@@ -125,10 +125,10 @@ class BlockDetails extends Component {
                   <div>
                     <TX
                       number={this.state.block.number}
-                      hash={JSON.parse(tx).hash}
-                      fr={JSON.parse(tx).fr}
-                      to={JSON.parse(tx).to}
-                      value={JSON.parse(tx).value}
+                      hash={tx.hash}
+                      fr={tx.fr}
+                      to={tx.to}
+                      value={tx.value}
                     ></TX>
                   </div>
                 );
@@ -35,8 +35,7 @@ class TXDetails extends Component {
     fetch(tx_link)
       .then((response) => response.json())
       .then((tx) => {
-        console.log(JSON.parse(tx.tx));
-        this.setState({ tx: JSON.parse(tx.tx) });
+        this.setState({ tx: tx });
       });
   }
 
@@ -73,7 +72,7 @@ class TXDetails extends Component {
           <img class="question" src={question} />
           <h5 class="label">Number:</h5>
         </div>
-        <h5 class="block-number">122234</h5>
+        <h5 class="block-number">{this.state.tx.number}</h5>
         <div class="hl"></div>
         <div class="txd-row time-row">
           <img class="question" src={question} />